@@ -0,0 +1,100 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use crate::{CmsError, CountMinSketch8};
+
+const MAX_FREQUENCY: u8 = 15;
+
+/// TinyLFU admission filter: caches record accesses, and ask `admit()` whether
+/// a candidate should replace the eviction victim.
+///
+/// Counters are capped at 15 and halved every `10 * capacity` accesses, so
+/// the filter tracks recent popularity.
+pub struct TinyLfuAdmission<K> {
+    cms: CountMinSketch8<K>,
+    sample_size: usize,
+    accesses: usize,
+}
+
+impl<K> TinyLfuAdmission<K>
+where
+    K: Hash,
+{
+    pub fn new(capacity: usize) -> Result<Self, CmsError> {
+        let cms = CountMinSketch8::new(capacity, 0.95, 2.0)?;
+        Ok(Self::with_sketch(cms, capacity))
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(capacity: usize, seeds: [u64; 4]) -> Result<Self, CmsError> {
+        let cms = CountMinSketch8::new_with_keys(capacity, 0.95, 2.0, seeds)?;
+        Ok(Self::with_sketch(cms, capacity))
+    }
+
+    pub(crate) fn with_sketch(mut cms: CountMinSketch8<K>, capacity: usize) -> Self {
+        cms.set_ceiling(MAX_FREQUENCY);
+        TinyLfuAdmission {
+            cms,
+            sample_size: capacity.saturating_mul(10).max(1),
+            accesses: 0,
+        }
+    }
+
+    pub fn record_access<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.cms.increment(key);
+        self.age(1);
+    }
+
+    // Folds in the accesses counted by `window`, a sketch built from the
+    // configuration of this filter
+    pub(crate) fn record_window(&mut self, window: &CountMinSketch8<K>, accesses: usize) {
+        self.cms
+            .merge(window)
+            .expect("The window shares the configuration of the filter");
+        self.age(accesses);
+    }
+
+    fn age(&mut self, accesses: usize) {
+        self.accesses += accesses;
+        if self.accesses >= self.sample_size {
+            self.cms.reset();
+            self.accesses = 0;
+        }
+    }
+
+    pub fn frequency<Q>(&self, key: &Q) -> u8
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.cms.estimate(key)
+    }
+
+    /// Returns `true` if `candidate` has been seen more often than `victim`.
+    pub fn admit<Q>(&self, candidate: &Q, victim: &Q) -> bool
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.frequency(candidate) > self.frequency(victim)
+    }
+
+    pub fn clear(&mut self) {
+        self.cms.clear();
+        self.accesses = 0;
+    }
+
+    pub(crate) fn sketch(&self) -> &CountMinSketch8<K> {
+        &self.cms
+    }
+
+    pub(crate) fn clear_keep_seeds(&mut self) {
+        self.cms.clear_keep_seeds();
+        self.accesses = 0;
+    }
+}
@@ -0,0 +1,202 @@
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::Hash;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::{
+    key_hashes, row_offset, CmsError, CountMinSketch, Counter, DecayPolicy, FastHasher,
+    OverflowPolicy, Rounding, SketchConfig,
+};
+
+/// Atomic integer usable as a counter of `AtomicCountMinSketch`.
+pub trait AtomicCounter: Send + Sync {
+    type Value: Counter;
+
+    fn new(value: Self::Value) -> Self;
+
+    fn load(&self) -> Self::Value;
+
+    fn store(&self, value: Self::Value);
+
+    fn saturating_add(&self, value: Self::Value);
+}
+
+macro_rules! atomic_counter_impl {
+    ($Atomic:ty, $Counter:ty) => {
+        impl AtomicCounter for $Atomic {
+            type Value = $Counter;
+
+            #[inline]
+            fn new(value: $Counter) -> Self {
+                <$Atomic>::new(value)
+            }
+
+            #[inline]
+            fn load(&self) -> $Counter {
+                <$Atomic>::load(self, Ordering::Relaxed)
+            }
+
+            #[inline]
+            fn store(&self, value: $Counter) {
+                <$Atomic>::store(self, value, Ordering::Relaxed)
+            }
+
+            #[inline]
+            fn saturating_add(&self, value: $Counter) {
+                let _ = self.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |counter| {
+                    Some(counter.saturating_add(value))
+                });
+            }
+        }
+    };
+}
+
+atomic_counter_impl!(AtomicU32, u32);
+atomic_counter_impl!(AtomicU64, u64);
+
+/// Sketch that can be updated and queried concurrently through `&self`.
+///
+/// Every row is incremented: conservative update can't be applied atomically
+/// across rows without risking underestimates, so estimates are somewhat
+/// higher than with `CountMinSketch`. All accesses use relaxed ordering.
+pub struct AtomicCountMinSketch<K, A> {
+    counters: Vec<A>,
+    hashers: [FastHasher; 2],
+    mask: usize,
+    k_num: usize,
+    total: AtomicU64,
+    phantom_k: PhantomData<K>,
+}
+
+pub type AtomicCountMinSketch32<K> = AtomicCountMinSketch<K, AtomicU32>;
+pub type AtomicCountMinSketch64<K> = AtomicCountMinSketch<K, AtomicU64>;
+
+impl<K, A> AtomicCountMinSketch<K, A>
+where
+    K: Hash,
+    A: AtomicCounter,
+{
+    #[cfg(feature = "std")]
+    pub fn new(capacity: usize, probability: f64, tolerance: f64) -> Result<Self, CmsError> {
+        let (width, k_num) =
+            CountMinSketch::<K, A::Value>::dimensions(capacity, probability, tolerance)?;
+        let hashers = [
+            CountMinSketch::<K, A::Value>::sip_new(),
+            CountMinSketch::<K, A::Value>::sip_new(),
+        ];
+        Ok(Self::with_hashers(hashers, width, k_num))
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let (width, k_num) =
+            CountMinSketch::<K, A::Value>::dimensions(capacity, probability, tolerance)?;
+        let hashers = [
+            FastHasher::new_with_keys(seeds[0], seeds[1]),
+            FastHasher::new_with_keys(seeds[2], seeds[3]),
+        ];
+        Ok(Self::with_hashers(hashers, width, k_num))
+    }
+
+    fn with_hashers(hashers: [FastHasher; 2], width: usize, k_num: usize) -> Self {
+        AtomicCountMinSketch {
+            counters: (0..width * k_num)
+                .map(|_| A::new(<A::Value>::ZERO))
+                .collect(),
+            hashers,
+            mask: width - 1,
+            k_num,
+            total: AtomicU64::new(0),
+            phantom_k: PhantomData,
+        }
+    }
+
+    pub fn config(&self) -> SketchConfig {
+        let (k0, k1) = self.hashers[0].keys();
+        let (k2, k3) = self.hashers[1].keys();
+        SketchConfig {
+            width: (self.mask + 1) as u64,
+            depth: self.k_num as u64,
+            seeds: [k0, k1, k2, k3],
+            counter_size: core::mem::size_of::<A::Value>() as u8,
+            ceiling: <A::Value>::MAX.to_u64(),
+            conservative: false,
+            rounding: Rounding::Down,
+            overflow_policy: OverflowPolicy::Saturate,
+            decay_policy: DecayPolicy::Never,
+        }
+    }
+
+    pub fn add<Q>(&self, key: &Q, value: A::Value)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let _ = self
+            .total
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+                Some(total.saturating_add(value.to_u64()))
+            });
+        let hashes = key_hashes(&self.hashers, key);
+        for k_i in 0..self.k_num {
+            self.counters[self.cell(&hashes, k_i)].saturating_add(value);
+        }
+    }
+
+    pub fn increment<Q>(&self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.add(key, <A::Value>::ONE)
+    }
+
+    pub fn estimate<Q>(&self, key: &Q) -> A::Value
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = key_hashes(&self.hashers, key);
+        (0..self.k_num)
+            .map(|k_i| self.counters[self.cell(&hashes, k_i)].load())
+            .min()
+            .unwrap_or(<A::Value>::ZERO)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Zeroes the counters, keeping the hash keys. Concurrent updates may or
+    /// may not survive.
+    pub fn clear(&self) {
+        for counter in &self.counters {
+            counter.store(<A::Value>::ZERO);
+        }
+        self.total.store(0, Ordering::Relaxed);
+    }
+
+    /// Copies the current counters into a regular sketch, for example to
+    /// serialize or merge it. The copy isn't an atomic snapshot.
+    pub fn snapshot(&self) -> CountMinSketch<K, A::Value> {
+        let mut cms = CountMinSketch::from_config(&self.config())
+            .expect("Configuration matches the counter type");
+        for (counter, atomic) in cms.counters.as_mut_slice().iter_mut().zip(&self.counters) {
+            *counter = atomic.load();
+        }
+        cms.total = self.total();
+        cms
+    }
+
+    #[inline]
+    fn cell(&self, hashes: &[u64; 2], k_i: usize) -> usize {
+        k_i * (self.mask + 1) + row_offset(hashes, k_i, self.mask)
+    }
+}
@@ -0,0 +1,195 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::{max, min};
+use core::hash::Hash;
+use core::marker::PhantomData;
+use core::mem;
+
+use crate::{
+    key_hashes, row_index, CmsError, CountMinSketch, Counter, FastHasher, Incompatibility,
+};
+
+const CACHE_LINE: usize = 64;
+
+/// Count-min sketch laid out so that all the counters of a key share a
+/// 64-byte cache line.
+///
+/// The first hash selects a line, which is split into one segment per row,
+/// and every row picks a counter within its segment. An update or a query
+/// touches a single cache line instead of one per row, at the price of
+/// accuracy: keys sharing a line collide more often than in
+/// `CountMinSketch`. Updates are conservative.
+///
+/// The depth can't exceed the number of counters per line, e.g. 8 for
+/// `u64` counters.
+pub struct BlockedCountMinSketch<K, C> {
+    counters: Vec<C>,
+    // Index of the first counter of the first line, which is aligned
+    start: usize,
+    line_mask: usize,
+    segment_mask: usize,
+    k_num: usize,
+    hashers: [FastHasher; 2],
+    phantom_k: PhantomData<K>,
+}
+
+impl<K, C> BlockedCountMinSketch<K, C>
+where
+    K: Hash,
+    C: Counter,
+{
+    /// Sized like `CountMinSketch::new()`, for the same memory usage.
+    #[cfg(feature = "std")]
+    pub fn new(capacity: usize, probability: f64, tolerance: f64) -> Result<Self, CmsError> {
+        let hashers = [
+            CountMinSketch::<K, C>::sip_new(),
+            CountMinSketch::<K, C>::sip_new(),
+        ];
+        Self::with_hashers(capacity, probability, tolerance, hashers)
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let hashers = [
+            FastHasher::new_with_keys(seeds[0], seeds[1]),
+            FastHasher::new_with_keys(seeds[2], seeds[3]),
+        ];
+        Self::with_hashers(capacity, probability, tolerance, hashers)
+    }
+
+    fn with_hashers(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        hashers: [FastHasher; 2],
+    ) -> Result<Self, CmsError> {
+        let (width, k_num) = CountMinSketch::<K, C>::dimensions(capacity, probability, tolerance)?;
+        let per_line = CACHE_LINE / mem::size_of::<C>();
+        if k_num > per_line {
+            return Err(CmsError::Depth(k_num as u64));
+        }
+        // Rounded down to a power of two
+        let segment = 1 << (usize::BITS - 1 - (per_line / k_num).leading_zeros());
+        let lines = max(1, width / segment);
+        // One spare line, so that the first one can be aligned
+        let counters = vec![C::ZERO; (lines + 1) * per_line];
+        let start = min(counters.as_ptr().align_offset(CACHE_LINE), per_line);
+        Ok(BlockedCountMinSketch {
+            counters,
+            start,
+            line_mask: lines - 1,
+            segment_mask: segment - 1,
+            k_num,
+            hashers,
+            phantom_k: PhantomData,
+        })
+    }
+
+    /// Number of counters a row of a key can map to, as with
+    /// `CountMinSketch::width()`.
+    pub fn width(&self) -> usize {
+        (self.line_mask + 1) * (self.segment_mask + 1)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.k_num
+    }
+
+    pub fn add<Q>(&mut self, key: &Q, value: C)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = key_hashes(&self.hashers, key);
+        let line = self.line(&hashes);
+        let lowest = (0..self.k_num)
+            .map(|k_i| self.counters[line + self.cell(&hashes, k_i)])
+            .fold(C::MAX, min);
+        let target = lowest.saturating_add(value);
+        for k_i in 0..self.k_num {
+            let cell = line + self.cell(&hashes, k_i);
+            let counter = &mut self.counters[cell];
+            if *counter < target {
+                *counter = target;
+            }
+        }
+    }
+
+    pub fn increment<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.add(key, C::ONE)
+    }
+
+    pub fn estimate<Q>(&self, key: &Q) -> C
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = key_hashes(&self.hashers, key);
+        let line = self.line(&hashes);
+        (0..self.k_num)
+            .map(|k_i| self.counters[line + self.cell(&hashes, k_i)])
+            .fold(C::MAX, min)
+    }
+
+    /// Adds the counters of `other`, which must have the same dimensions
+    /// and seeds.
+    pub fn merge(&mut self, other: &BlockedCountMinSketch<K, C>) -> Result<(), Incompatibility> {
+        if self.width() != other.width() {
+            return Err(Incompatibility::Width {
+                ours: self.width(),
+                theirs: other.width(),
+            });
+        }
+        if self.k_num != other.k_num {
+            return Err(Incompatibility::Depth {
+                ours: self.k_num,
+                theirs: other.k_num,
+            });
+        }
+        if self.hashers[0].keys() != other.hashers[0].keys()
+            || self.hashers[1].keys() != other.hashers[1].keys()
+        {
+            return Err(Incompatibility::Seeds);
+        }
+        // Both sketches may not have the same alignment padding
+        let len = self.counters.len() - CACHE_LINE / mem::size_of::<C>();
+        C::add_slice(
+            &mut self.counters[self.start..self.start + len],
+            &other.counters[other.start..other.start + len],
+            C::MAX,
+        );
+        Ok(())
+    }
+
+    /// Zeroes the counters, keeping the hash keys.
+    pub fn clear(&mut self) {
+        self.counters.fill(C::ZERO);
+    }
+
+    // Index of the first counter of the line of `hashes`
+    #[inline]
+    fn line(&self, hashes: &[u64; 2]) -> usize {
+        let line = hashes[0] as usize & self.line_mask;
+        self.start + line * (CACHE_LINE / mem::size_of::<C>())
+    }
+
+    // Position of the counter of row `k_i` within the line. The low bits of
+    // the first hash select the line, so the high bits of the row hash are
+    // used here.
+    #[inline]
+    fn cell(&self, hashes: &[u64; 2], k_i: usize) -> usize {
+        let offset = (row_index(hashes, k_i, u64::MAX) >> 32) as usize & self.segment_mask;
+        k_i * (self.segment_mask + 1) + offset
+    }
+}
@@ -0,0 +1,211 @@
+use core::hash::Hash;
+use core::marker::PhantomData;
+
+use crate::clock::SharedClock;
+use crate::{
+    Clock, CmsError, CountMinSketch, Counter, DecayPolicy, OverflowPolicy, Rounding,
+    SaturationError, SipBuildHasher, VecStorage,
+};
+
+/// Configures a `CountMinSketch` before building it.
+///
+/// The width comes either from `width()` or from `capacity()` and
+/// `tolerance()`, and the depth either from `depth()` or from
+/// `probability()`, as in `CountMinSketch::new()`. Setting both ways for
+/// the same dimension is an error.
+#[derive(Clone, Debug)]
+pub struct CountMinSketchBuilder<K, C> {
+    capacity: Option<usize>,
+    probability: Option<f64>,
+    tolerance: Option<f64>,
+    width: Option<usize>,
+    depth: Option<usize>,
+    seeds: Option<[u64; 4]>,
+    conservative: bool,
+    ceiling: Option<C>,
+    rounding: Rounding,
+    decay_policy: DecayPolicy,
+    clock: Option<SharedClock>,
+    overflow_policy: OverflowPolicy,
+    overflow_handler: Option<fn(SaturationError)>,
+    phantom_k: PhantomData<K>,
+}
+
+impl<K, C> Default for CountMinSketchBuilder<K, C>
+where
+    K: Hash,
+    C: Counter,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, C> CountMinSketchBuilder<K, C>
+where
+    K: Hash,
+    C: Counter,
+{
+    pub fn new() -> Self {
+        CountMinSketchBuilder {
+            capacity: None,
+            probability: None,
+            tolerance: None,
+            width: None,
+            depth: None,
+            seeds: None,
+            conservative: true,
+            ceiling: None,
+            rounding: Rounding::Down,
+            decay_policy: DecayPolicy::Never,
+            clock: None,
+            overflow_policy: OverflowPolicy::Saturate,
+            overflow_handler: None,
+            phantom_k: PhantomData,
+        }
+    }
+
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.probability = Some(probability);
+        self
+    }
+
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = Some(tolerance);
+        self
+    }
+
+    /// Number of counters per row, which must be a power of two.
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// Number of rows.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Hash keys, in the order of `SketchConfig::seeds`. They are random
+    /// by default, and required without the `std` feature.
+    pub fn seeds(mut self, seeds: [u64; 4]) -> Self {
+        self.seeds = Some(seeds);
+        self
+    }
+
+    /// Only raises the lowest counters of a key on updates (the default).
+    ///
+    /// Disabling it increments every row, which overestimates more but
+    /// keeps each row an exact sum of the updates.
+    pub fn conservative_update(mut self, conservative: bool) -> Self {
+        self.conservative = conservative;
+        self
+    }
+
+    pub fn ceiling(mut self, ceiling: C) -> Self {
+        self.ceiling = Some(ceiling);
+        self
+    }
+
+    pub fn rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// When the sketch ages itself. `EveryDuration` requires a `clock()`,
+    /// and its first period starts when the sketch is built.
+    pub fn decay_policy(mut self, decay_policy: DecayPolicy) -> Self {
+        self.decay_policy = decay_policy;
+        self
+    }
+
+    /// Time source of `DecayPolicy::EveryDuration`.
+    pub fn clock<T>(mut self, clock: T) -> Self
+    where
+        T: Clock + Send + Sync + 'static,
+    {
+        self.clock = Some(SharedClock::new(clock));
+        self
+    }
+
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// See `CountMinSketch::set_overflow_handler()`.
+    pub fn overflow_handler(mut self, handler: fn(SaturationError)) -> Self {
+        self.overflow_handler = Some(handler);
+        self
+    }
+
+    pub fn build(self) -> Result<CountMinSketch<K, C>, CmsError> {
+        let width = match (self.width, self.capacity, self.tolerance) {
+            (Some(width), None, None) => width,
+            (Some(_), _, _) => {
+                return Err(CmsError::Builder(
+                    "Width can't be combined with capacity and tolerance",
+                ))
+            }
+            (None, Some(capacity), Some(tolerance)) => {
+                CountMinSketch::<K, C>::optimal_width(capacity, tolerance)?
+            }
+            (None, _, _) => {
+                return Err(CmsError::Builder(
+                    "Either width or capacity and tolerance are required",
+                ))
+            }
+        };
+        let k_num = match (self.depth, self.probability) {
+            (Some(depth), None) => depth,
+            (Some(_), Some(_)) => {
+                return Err(CmsError::Builder(
+                    "Depth can't be combined with probability",
+                ))
+            }
+            (None, Some(probability)) => CountMinSketch::<K, C>::optimal_depth(probability)?,
+            (None, None) => {
+                return Err(CmsError::Builder("Either depth or probability is required"))
+            }
+        };
+        CountMinSketch::<K, C>::check_dimensions(width, k_num)?;
+        let hashers = match self.seeds {
+            Some(seeds) => [
+                SipBuildHasher::new_with_keys(seeds[0], seeds[1]),
+                SipBuildHasher::new_with_keys(seeds[2], seeds[3]),
+            ],
+            #[cfg(feature = "std")]
+            None => [
+                CountMinSketch::<K, C>::sip_new().into(),
+                CountMinSketch::<K, C>::sip_new().into(),
+            ],
+            #[cfg(not(feature = "std"))]
+            None => {
+                return Err(CmsError::Builder(
+                    "Seeds are required without the std feature",
+                ))
+            }
+        };
+        let counters = VecStorage::new(width, k_num);
+        let mut cms = CountMinSketch::with_storage(counters, hashers, width, k_num);
+        cms.conservative = self.conservative;
+        cms.ceiling = self.ceiling.unwrap_or(C::MAX);
+        cms.rounding = self.rounding;
+        cms.decay_policy = self.decay_policy;
+        if let Some(clock) = self.clock {
+            cms.decayed_at = clock.now();
+            cms.clock = Some(clock);
+        } else if let DecayPolicy::EveryDuration(_) = self.decay_policy {
+            return Err(CmsError::Builder("EveryDuration requires a clock"));
+        }
+        cms.overflow_policy = self.overflow_policy;
+        cms.overflow_handler = self.overflow_handler;
+        Ok(cms)
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::time::Duration;
+
+use crate::{DecayPolicy, OverflowPolicy, Rounding};
+
+const MAGIC: [u8; 4] = *b"CMSC";
+const VERSION: u8 = 2;
+// Version 1 stopped after the ceiling, without the update policies
+const V1_LEN: usize = 4 + 1 + 1 + 8 * 7;
+pub(crate) const CONFIG_LEN: usize = V1_LEN + 4 + 8;
+
+/// Everything needed to build merge-compatible empty sketches: dimensions,
+/// hash keys, counter type and update policies, but no counters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SketchConfig {
+    pub width: u64,
+    pub depth: u64,
+    pub seeds: [u64; 4],
+    pub counter_size: u8,
+    pub ceiling: u64,
+    pub conservative: bool,
+    pub rounding: Rounding,
+    pub overflow_policy: OverflowPolicy,
+    /// `EveryDuration` only ages sketches built from the configuration once
+    /// a clock is given with `set_decay_policy()`, or through `add_at()`.
+    pub decay_policy: DecayPolicy,
+}
+
+impl SketchConfig {
+    /// 74 bytes: the magic `CMSC`, a version byte (2), the
+    /// counter size, then `width`, `depth`, the four seeds and `ceiling` as
+    /// little-endian `u64`s. Policies follow as one byte each for
+    /// conservative update, rounding, overflow policy and decay policy, in
+    /// declaration order of the variants, and the `u64` parameter of the
+    /// decay policy: the number of inserts, or the period in nanoseconds.
+    ///
+    /// `from_bytes()` also reads version 1, which stopped after `ceiling`,
+    /// with the default policies and conservative update enabled.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; CONFIG_LEN];
+        self.write_to(&mut bytes);
+        bytes
+    }
+
+    // `out` is `CONFIG_LEN` bytes long
+    pub(crate) fn write_to(&self, out: &mut [u8]) {
+        out[..4].copy_from_slice(&MAGIC);
+        out[4] = VERSION;
+        out[5] = self.counter_size;
+        let [s0, s1, s2, s3] = self.seeds;
+        let fields = [self.width, self.depth, s0, s1, s2, s3, self.ceiling];
+        for (le, field) in out[6..V1_LEN].chunks_exact_mut(8).zip(&fields) {
+            le.copy_from_slice(&field.to_le_bytes());
+        }
+        out[V1_LEN] = self.conservative as u8;
+        out[V1_LEN + 1] = match self.rounding {
+            Rounding::Down => 0,
+            Rounding::HalfUp => 1,
+            Rounding::KeepNonZero => 2,
+        };
+        out[V1_LEN + 2] = match self.overflow_policy {
+            OverflowPolicy::Saturate => 0,
+            OverflowPolicy::Error => 1,
+            OverflowPolicy::Wrap => 2,
+        };
+        let (decay, param) = match self.decay_policy {
+            DecayPolicy::Never => (0, 0),
+            DecayPolicy::EveryNInserts(n) => (1, n),
+            DecayPolicy::EveryDuration(period) => (2, period.as_nanos() as u64),
+        };
+        out[V1_LEN + 3] = decay;
+        out[V1_LEN + 4..CONFIG_LEN].copy_from_slice(&param.to_le_bytes());
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < V1_LEN || bytes[..4] != MAGIC {
+            return Err("Not a sketch configuration");
+        }
+        let len = match bytes[4] {
+            1 => V1_LEN,
+            VERSION => CONFIG_LEN,
+            _ => return Err("Unsupported configuration version"),
+        };
+        if bytes.len() != len {
+            return Err("Not a sketch configuration");
+        }
+        let u64_at = |i: usize| {
+            let start = 6 + i * 8;
+            u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap_or_default())
+        };
+        let mut config = SketchConfig {
+            counter_size: bytes[5],
+            width: u64_at(0),
+            depth: u64_at(1),
+            seeds: [u64_at(2), u64_at(3), u64_at(4), u64_at(5)],
+            ceiling: u64_at(6),
+            conservative: true,
+            rounding: Rounding::Down,
+            overflow_policy: OverflowPolicy::Saturate,
+            decay_policy: DecayPolicy::Never,
+        };
+        if len == V1_LEN {
+            return Ok(config);
+        }
+        config.conservative = match bytes[V1_LEN] {
+            0 => false,
+            1 => true,
+            _ => return Err("Invalid conservative update flag"),
+        };
+        config.rounding = match bytes[V1_LEN + 1] {
+            0 => Rounding::Down,
+            1 => Rounding::HalfUp,
+            2 => Rounding::KeepNonZero,
+            _ => return Err("Unknown rounding policy"),
+        };
+        config.overflow_policy = match bytes[V1_LEN + 2] {
+            0 => OverflowPolicy::Saturate,
+            1 => OverflowPolicy::Error,
+            2 => OverflowPolicy::Wrap,
+            _ => return Err("Unknown overflow policy"),
+        };
+        let param =
+            u64::from_le_bytes(bytes[V1_LEN + 4..CONFIG_LEN].try_into().unwrap_or_default());
+        config.decay_policy = match bytes[V1_LEN + 3] {
+            0 => DecayPolicy::Never,
+            1 => DecayPolicy::EveryNInserts(param),
+            2 => DecayPolicy::EveryDuration(Duration::from_nanos(param)),
+            _ => return Err("Unknown decay policy"),
+        };
+        Ok(config)
+    }
+}
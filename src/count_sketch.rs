@@ -0,0 +1,152 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::Hash;
+use core::marker::PhantomData;
+
+use crate::{key_hashes, row_index, CmsError, CountMinSketch64, FastHasher, Incompatibility};
+
+/// Count sketch: every row adds `value` with a per-key random sign, and
+/// estimates are the median of the signed rows.
+///
+/// Unlike `CountMinSketch`, estimates are unbiased, so they can be lower
+/// than the true count, and the error is relative to the L2 norm of the
+/// stream rather than its total. Sized like `CountMinSketch::new()`; an odd
+/// depth gives a true median.
+pub struct CountSketch<K> {
+    counters: Vec<i64>,
+    hashers: [FastHasher; 2],
+    mask: usize,
+    k_num: usize,
+    phantom_k: PhantomData<K>,
+}
+
+impl<K> CountSketch<K>
+where
+    K: Hash,
+{
+    #[cfg(feature = "std")]
+    pub fn new(capacity: usize, probability: f64, tolerance: f64) -> Result<Self, CmsError> {
+        let (width, k_num) = CountMinSketch64::<K>::dimensions(capacity, probability, tolerance)?;
+        let hashers = [
+            CountMinSketch64::<K>::sip_new(),
+            CountMinSketch64::<K>::sip_new(),
+        ];
+        Ok(Self::with_hashers(hashers, width, k_num))
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let (width, k_num) = CountMinSketch64::<K>::dimensions(capacity, probability, tolerance)?;
+        let hashers = [
+            FastHasher::new_with_keys(seeds[0], seeds[1]),
+            FastHasher::new_with_keys(seeds[2], seeds[3]),
+        ];
+        Ok(Self::with_hashers(hashers, width, k_num))
+    }
+
+    fn with_hashers(hashers: [FastHasher; 2], width: usize, k_num: usize) -> Self {
+        CountSketch {
+            counters: vec![0; width * k_num],
+            hashers,
+            mask: width - 1,
+            k_num,
+            phantom_k: PhantomData,
+        }
+    }
+
+    /// Adds `value`, which may be negative, to the count of `key`.
+    /// Counters wrap on overflow.
+    pub fn add<Q>(&mut self, key: &Q, value: i64)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = key_hashes(&self.hashers, key);
+        for k_i in 0..self.k_num {
+            let (cell, sign) = self.cell(&hashes, k_i);
+            self.counters[cell] = self.counters[cell].wrapping_add(sign.wrapping_mul(value));
+        }
+    }
+
+    pub fn increment<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.add(key, 1)
+    }
+
+    /// Median of the signed counters of `key`. With an even depth, the
+    /// mean of the two middle values, rounded toward zero.
+    pub fn estimate<Q>(&self, key: &Q) -> i64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = key_hashes(&self.hashers, key);
+        let mut values: Vec<i64> = (0..self.k_num)
+            .map(|k_i| {
+                let (cell, sign) = self.cell(&hashes, k_i);
+                self.counters[cell].wrapping_mul(sign)
+            })
+            .collect();
+        values.sort_unstable();
+        let mid = self.k_num / 2;
+        if self.k_num % 2 == 1 {
+            values[mid]
+        } else {
+            ((values[mid - 1] as i128 + values[mid] as i128) / 2) as i64
+        }
+    }
+
+    /// Adds the counters of `other`, which must have the same dimensions
+    /// and seeds. The result is the sketch of both streams.
+    pub fn merge(&mut self, other: &CountSketch<K>) -> Result<(), Incompatibility> {
+        if self.mask != other.mask {
+            return Err(Incompatibility::Width {
+                ours: self.mask + 1,
+                theirs: other.mask + 1,
+            });
+        }
+        if self.k_num != other.k_num {
+            return Err(Incompatibility::Depth {
+                ours: self.k_num,
+                theirs: other.k_num,
+            });
+        }
+        if self.hashers[0].keys() != other.hashers[0].keys()
+            || self.hashers[1].keys() != other.hashers[1].keys()
+        {
+            return Err(Incompatibility::Seeds);
+        }
+        for (counter, &value) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.wrapping_add(value);
+        }
+        Ok(())
+    }
+
+    /// Zeroes the counters, keeping the hash keys.
+    pub fn clear(&mut self) {
+        for counter in &mut self.counters {
+            *counter = 0;
+        }
+    }
+
+    // The low bits of the row hash select the cell, the top bit the sign.
+    #[inline]
+    fn cell(&self, hashes: &[u64; 2], k_i: usize) -> (usize, i64) {
+        let hash = row_index(hashes, k_i, u64::MAX);
+        let sign = if hash >> 63 == 0 { 1 } else { -1 };
+        (
+            k_i * (self.mask + 1) + (hash & self.mask as u64) as usize,
+            sign,
+        )
+    }
+}
@@ -0,0 +1,111 @@
+use core::convert::TryFrom;
+use core::fmt::Debug;
+use core::hash::Hash;
+
+use crate::{simd, Rounding};
+
+/// Unsigned integer type usable as a sketch counter.
+pub trait Counter: Copy + Ord + Hash + Default + Debug + Send + Sync + 'static {
+    const ZERO: Self;
+    const ONE: Self;
+    const MAX: Self;
+
+    /// Little-endian representation, as used by the serialization formats.
+    type Bytes: AsRef<[u8]> + AsMut<[u8]> + Default;
+
+    fn saturating_add(self, other: Self) -> Self;
+
+    fn saturating_sub(self, other: Self) -> Self;
+
+    fn wrapping_add(self, other: Self) -> Self;
+
+    fn to_u64(self) -> u64;
+
+    /// Returns `None` if `value` doesn't fit.
+    fn from_u64(value: u64) -> Option<Self>;
+
+    fn to_le_bytes(self) -> Self::Bytes;
+
+    fn from_le_bytes(bytes: Self::Bytes) -> Self;
+
+    /// Halves every counter of `counters`, as `reset()` does.
+    fn halve_slice(counters: &mut [Self], rounding: Rounding) {
+        simd::halve_scalar(counters, rounding)
+    }
+
+    /// Adds `values` to `counters`, element-wise, capping them at `ceiling`.
+    fn add_slice(counters: &mut [Self], values: &[Self], ceiling: Self) {
+        simd::add_scalar(counters, values, ceiling)
+    }
+}
+
+macro_rules! counter_impl {
+    ($Counter:ty) => {
+        counter_impl!($Counter, {});
+    };
+    ($Counter:ty, $halve:path, $add:path) => {
+        counter_impl!($Counter, {
+            #[inline]
+            fn halve_slice(counters: &mut [Self], rounding: Rounding) {
+                $halve(counters, rounding)
+            }
+
+            #[inline]
+            fn add_slice(counters: &mut [Self], values: &[Self], ceiling: Self) {
+                $add(counters, values, ceiling)
+            }
+        });
+    };
+    ($Counter:ty, { $($slice_ops:tt)* }) => {
+        impl Counter for $Counter {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+            const MAX: Self = <$Counter>::MAX;
+
+            type Bytes = [u8; core::mem::size_of::<$Counter>()];
+
+            #[inline]
+            fn saturating_add(self, other: Self) -> Self {
+                <$Counter>::saturating_add(self, other)
+            }
+
+            #[inline]
+            fn saturating_sub(self, other: Self) -> Self {
+                <$Counter>::saturating_sub(self, other)
+            }
+
+            #[inline]
+            fn wrapping_add(self, other: Self) -> Self {
+                <$Counter>::wrapping_add(self, other)
+            }
+
+            #[inline]
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            #[inline]
+            fn from_u64(value: u64) -> Option<Self> {
+                <$Counter>::try_from(value).ok()
+            }
+
+            #[inline]
+            fn to_le_bytes(self) -> Self::Bytes {
+                <$Counter>::to_le_bytes(self)
+            }
+
+            #[inline]
+            fn from_le_bytes(bytes: Self::Bytes) -> Self {
+                <$Counter>::from_le_bytes(bytes)
+            }
+
+            $($slice_ops)*
+        }
+    };
+}
+
+// Vectorized where the target supports it
+counter_impl!(u8, simd::halve_u8, simd::add_u8);
+counter_impl!(u16, simd::halve_u16, simd::add_u16);
+counter_impl!(u32);
+counter_impl!(u64);
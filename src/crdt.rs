@@ -0,0 +1,166 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::Hash;
+
+use crate::{CmsError, CountMinSketch64, Incompatibility, SketchConfig};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReplicaState {
+    pub version: u64,
+    pub total: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrdtError {
+    Incompatible(Incompatibility),
+    /// The same replica version was seen with different totals: two replicas
+    /// share an identifier, or a replica lost its state.
+    Conflict {
+        replica_id: u64,
+    },
+}
+
+impl fmt::Display for CrdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrdtError::Incompatible(incompatibility) => incompatibility.fmt(f),
+            CrdtError::Conflict { replica_id } => {
+                write!(f, "Conflicting states for replica {}", replica_id)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CrdtError {}
+
+/// Sketch usable as a state-based CRDT, in the manner of a G-counter.
+///
+/// Every replica only updates its own counters, with the standard update,
+/// and keeps the latest counters it received from the other replicas.
+/// `join()` keeps the most recent version of each replica, which is
+/// commutative, associative and idempotent: replicas converge to the same
+/// state whatever the gossip order. Estimates are computed over the sum of
+/// all the replicas, so they count the events of every replica once.
+///
+/// All replicas must be created from the same `SketchConfig`. Each one
+/// holds a copy of the counters of every replica it heard of.
+pub struct CrdtSketch<K> {
+    replica_id: u64,
+    // Sum of the counters of all the replicas
+    cms: CountMinSketch64<K>,
+    replicas: BTreeMap<u64, ReplicaState>,
+    counters: BTreeMap<u64, Vec<u64>>,
+}
+
+impl<K> CrdtSketch<K>
+where
+    K: Hash,
+{
+    pub fn new(replica_id: u64, config: &SketchConfig) -> Result<Self, CmsError> {
+        let mut cms = CountMinSketch64::from_config(config)?;
+        cms.set_conservative_update(false);
+        Ok(CrdtSketch {
+            replica_id,
+            cms,
+            replicas: BTreeMap::new(),
+            counters: BTreeMap::new(),
+        })
+    }
+
+    pub fn replica_id(&self) -> u64 {
+        self.replica_id
+    }
+
+    pub fn add<Q>(&mut self, key: &Q, value: u64)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = self.cms.key_hashes(key);
+        self.cms.add_hashes(&hashes, value);
+        let width = self.cms.width();
+        let len = width * self.cms.depth();
+        let own = self
+            .counters
+            .entry(self.replica_id)
+            .or_insert_with(|| vec![0; len]);
+        for k_i in 0..self.cms.depth() {
+            let counter = &mut own[k_i * width + self.cms.row_offset(&hashes, k_i)];
+            *counter = counter.saturating_add(value);
+        }
+        let state = self.replicas.entry(self.replica_id).or_default();
+        state.version += 1;
+        state.total = state.total.saturating_add(value);
+    }
+
+    pub fn increment<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.add(key, 1)
+    }
+
+    pub fn estimate<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.cms.estimate(key)
+    }
+
+    pub fn replicas(&self) -> &BTreeMap<u64, ReplicaState> {
+        &self.replicas
+    }
+
+    /// Sum of all the replicas.
+    pub fn sketch(&self) -> &CountMinSketch64<K> {
+        &self.cms
+    }
+
+    pub fn join(&mut self, other: &Self) -> Result<(), CrdtError> {
+        self.cms
+            .is_mergeable(&other.cms)
+            .map_err(CrdtError::Incompatible)?;
+        for (replica_id, theirs) in &other.replicas {
+            if let Some(ours) = self.replicas.get(replica_id) {
+                if ours.version == theirs.version && ours.total != theirs.total {
+                    return Err(CrdtError::Conflict {
+                        replica_id: *replica_id,
+                    });
+                }
+            }
+        }
+        for (replica_id, theirs) in &other.replicas {
+            let ours = self.replicas.entry(*replica_id).or_default();
+            if theirs.version <= ours.version {
+                continue;
+            }
+            *ours = *theirs;
+            let new = &other.counters[replica_id];
+            let sum = self.cms.counters.as_mut_slice();
+            match self.counters.get(replica_id) {
+                Some(old) => {
+                    for ((sum, &old), &new) in sum.iter_mut().zip(old).zip(new) {
+                        *sum = sum.saturating_sub(old).saturating_add(new);
+                    }
+                }
+                None => {
+                    for (sum, &new) in sum.iter_mut().zip(new) {
+                        *sum = sum.saturating_add(new);
+                    }
+                }
+            }
+            self.counters.insert(*replica_id, new.clone());
+        }
+        self.cms.total = self
+            .replicas
+            .values()
+            .fold(0, |total, state| total.saturating_add(state.total));
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
+
+/// Global mutual exclusion primitive, typically disabling interrupts on a
+/// single-core microcontroller.
+///
+/// # Safety
+///
+/// While `with()` runs `f`, no other call to `with()` of the same
+/// implementation may run `f` concurrently, from any thread or interrupt handler.
+pub unsafe trait CriticalSection {
+    fn with<R>(f: impl FnOnce() -> R) -> R;
+}
+
+/// Sketch shared between threads or interrupt handlers, with every access
+/// going through the critical section `CS`.
+pub struct CriticalSectionSketch<T, CS> {
+    sketch: UnsafeCell<T>,
+    busy: Cell<bool>,
+    phantom_cs: PhantomData<CS>,
+}
+
+unsafe impl<T, CS> Sync for CriticalSectionSketch<T, CS>
+where
+    T: Send,
+    CS: CriticalSection,
+{
+}
+
+impl<T, CS> CriticalSectionSketch<T, CS>
+where
+    CS: CriticalSection,
+{
+    pub const fn new(sketch: T) -> Self {
+        CriticalSectionSketch {
+            sketch: UnsafeCell::new(sketch),
+            busy: Cell::new(false),
+            phantom_cs: PhantomData,
+        }
+    }
+
+    /// Runs `f` on the sketch inside the critical section.
+    ///
+    /// Returns `None` if called again from within `f`.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        CS::with(|| {
+            if self.busy.replace(true) {
+                return None;
+            }
+            let _busy = BusyGuard(&self.busy);
+            // Safety: `CS` serializes accesses, and `busy` rules out reentrancy
+            Some(f(unsafe { &mut *self.sketch.get() }))
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.sketch.into_inner()
+    }
+}
+
+// Clears `busy` when `f` returns, and also if it panics
+struct BusyGuard<'a>(&'a Cell<bool>);
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
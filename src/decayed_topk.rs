@@ -0,0 +1,147 @@
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::{Clock, CmsError, CountMinSketch64};
+
+// Fixed-point scale applied to every weight, so that repeated halving keeps
+// some precision.
+const SCALE: f64 = 1024.0;
+
+/// Top-k tracker where every occurrence loses half its weight after each
+/// `half_life`, so the reported keys are the trending ones rather than the
+/// all-time leaders.
+///
+/// Weights are forward-decayed: new occurrences are scaled up relative to a
+/// landmark instead of decaying the whole sketch, so decay alone never
+/// reorders the tracked keys. The landmark moves by one half-life at a time,
+/// halving the sketch with `reset()`.
+pub struct DecayedTopK<K> {
+    cms: CountMinSketch64<K>,
+    k: usize,
+    half_life: Duration,
+    landmark: Duration,
+    top: Vec<(K, u64)>,
+}
+
+impl<K> DecayedTopK<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        k: usize,
+        half_life: Duration,
+    ) -> Result<Self, CmsError> {
+        let cms = CountMinSketch64::new(capacity, probability, tolerance)?;
+        Self::with_sketch(cms, k, half_life)
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        k: usize,
+        half_life: Duration,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let cms = CountMinSketch64::new_with_keys(capacity, probability, tolerance, seeds)?;
+        Self::with_sketch(cms, k, half_life)
+    }
+
+    fn with_sketch(
+        cms: CountMinSketch64<K>,
+        k: usize,
+        half_life: Duration,
+    ) -> Result<Self, CmsError> {
+        if half_life.is_zero() {
+            return Err(CmsError::ZeroDuration);
+        }
+        Ok(DecayedTopK {
+            cms,
+            k,
+            half_life,
+            landmark: Duration::ZERO,
+            top: Vec::with_capacity(k),
+        })
+    }
+
+    pub fn half_life(&self) -> Duration {
+        self.half_life
+    }
+
+    pub fn add<T>(&mut self, key: &K, value: u64, clock: T)
+    where
+        T: Clock,
+    {
+        let now = clock.now();
+        self.advance(now);
+        let elapsed = now.saturating_sub(self.landmark).as_secs_f64();
+        let factor = (elapsed / self.half_life.as_secs_f64()).exp2();
+        let weight = (value as f64 * SCALE * factor).round() as u64;
+        self.cms.add(key, weight);
+        let score = self.cms.estimate(key);
+
+        if let Some(pos) = self.top.iter().position(|(k, _)| k == key) {
+            self.top[pos].1 = score;
+        } else if self.top.len() < self.k {
+            self.top.push((key.clone(), score));
+        } else if let Some(last) = self.top.last_mut() {
+            if score <= last.1 {
+                return;
+            }
+            *last = (key.clone(), score);
+        } else {
+            return;
+        }
+        // Stable sort: equal scores keep the order in which they got there
+        self.top.sort_by_key(|e| std::cmp::Reverse(e.1));
+    }
+
+    pub fn increment<T>(&mut self, key: &K, clock: T)
+    where
+        T: Clock,
+    {
+        self.add(key, 1, clock)
+    }
+
+    /// Current top-k keys with their decayed weights, heaviest first.
+    pub fn top<T>(&self, clock: T) -> Vec<(&K, f64)>
+    where
+        T: Clock,
+    {
+        let now = clock.now();
+        let elapsed = now.saturating_sub(self.landmark).as_secs_f64();
+        let factor = (elapsed / self.half_life.as_secs_f64()).exp2();
+        self.top
+            .iter()
+            .map(|(key, score)| (key, *score as f64 / SCALE / factor))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.cms.clear();
+        self.top.clear();
+        self.landmark = Duration::ZERO;
+    }
+
+    // Moves the landmark forward so that the forward-decay factor stays below 2
+    fn advance(&mut self, now: Duration) {
+        if now < self.landmark + self.half_life {
+            return;
+        }
+        let half_life = self.half_life.as_nanos();
+        let halvings = (now - self.landmark).as_nanos() / half_life;
+        self.landmark =
+            Duration::from_nanos((self.landmark.as_nanos() + halvings * half_life) as u64);
+        for _ in 0..halvings.min(u64::BITS as u128) {
+            self.cms.reset();
+            for (_, score) in self.top.iter_mut() {
+                *score /= 2;
+            }
+        }
+    }
+}
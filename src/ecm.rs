@@ -0,0 +1,236 @@
+use std::borrow::Borrow;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::{key_hashes, row_offset, Clock, CmsError, CountMinSketch64, FastHasher};
+
+/// Exponential histogram counting events over a sliding window.
+#[derive(Clone, Debug, Default)]
+struct ExpHistogram {
+    // (timestamp of the most recent event, event count), oldest first;
+    // counts are powers of two, non-increasing
+    buckets: VecDeque<(Duration, u64)>,
+}
+
+impl ExpHistogram {
+    fn expire(&mut self, now: Duration, window: Duration) {
+        while let Some(&(timestamp, _)) = self.buckets.front() {
+            if now.saturating_sub(timestamp) < window {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    // Same as inserting `value` events one at a time, each merging the two
+    // oldest buckets of a size once there are more than `max_per_size`, but
+    // in a single pass over the sizes. The new buckets of a size all hold
+    // events at `now`, so they are only counted until few enough remain.
+    fn insert(&mut self, now: Duration, value: u64, max_per_size: usize) {
+        let max_per_size = max_per_size as u64;
+        let mut levels = Vec::new();
+        // Buckets of the current size coming from merges of older buckets,
+        // oldest first, then the number of buckets of that size at `now`
+        let mut carried: Vec<Duration> = Vec::new();
+        let mut new_buckets = value;
+        let mut size = 1u64;
+        while !carried.is_empty() || new_buckets > 0 {
+            let same_size = self
+                .buckets
+                .iter()
+                .rev()
+                .take_while(|b| b.1 == size)
+                .count();
+            let split = self.buckets.len() - same_size;
+            let mut explicit: Vec<Duration> = self.buckets.drain(split..).map(|b| b.0).collect();
+            explicit.append(&mut carried);
+            let len = explicit.len() as u64;
+            let total = len + new_buckets;
+            let merges = if total > max_per_size {
+                (total - max_per_size).div_ceil(2)
+            } else {
+                0
+            };
+            // A merged bucket takes the timestamp of the newer one
+            let explicit_merges = min(merges, len / 2);
+            carried = (0..explicit_merges as usize)
+                .map(|i| explicit[2 * i + 1])
+                .collect();
+            let merged = (2 * merges).min(len) as usize;
+            let level: Vec<(Duration, u64)> = explicit[merged..]
+                .iter()
+                .map(|&timestamp| (timestamp, size))
+                .chain((0..total - 2 * merges - (len - merged as u64)).map(|_| (now, size)))
+                .collect();
+            levels.push(level);
+            new_buckets = merges - explicit_merges;
+            size = size.saturating_mul(2);
+        }
+        for level in levels.into_iter().rev() {
+            self.buckets.extend(level);
+        }
+    }
+
+    fn count(&self, now: Duration, window: Duration) -> u64 {
+        let mut live = self
+            .buckets
+            .iter()
+            .filter(|&&(timestamp, _)| now.saturating_sub(timestamp) < window);
+        let oldest = match live.next() {
+            None => return 0,
+            Some(&(_, size)) => size,
+        };
+        // Only part of the oldest bucket may still be in the window
+        live.map(|&(_, size)| size).sum::<u64>() + oldest.div_ceil(2)
+    }
+}
+
+/// ECM-sketch: a count-min sketch whose cells are exponential histograms,
+/// answering "how many times was this key seen during the last `window`".
+///
+/// Each cell count is within a relative error of `window_error` of the true
+/// count, on top of the usual count-min collision error.
+pub struct EcmSketch<K> {
+    cells: Vec<ExpHistogram>,
+    hashers: [FastHasher; 2],
+    mask: usize,
+    k_num: usize,
+    window: Duration,
+    max_per_size: usize,
+    phantom_k: PhantomData<K>,
+}
+
+impl<K> EcmSketch<K>
+where
+    K: Hash,
+{
+    pub fn new(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        window: Duration,
+        window_error: f64,
+    ) -> Result<Self, CmsError> {
+        let hashers = [
+            CountMinSketch64::<K>::sip_new(),
+            CountMinSketch64::<K>::sip_new(),
+        ];
+        Self::with_hashers(
+            capacity,
+            probability,
+            tolerance,
+            window,
+            window_error,
+            hashers,
+        )
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        window: Duration,
+        window_error: f64,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let hashers = [
+            FastHasher::new_with_keys(seeds[0], seeds[1]),
+            FastHasher::new_with_keys(seeds[2], seeds[3]),
+        ];
+        Self::with_hashers(
+            capacity,
+            probability,
+            tolerance,
+            window,
+            window_error,
+            hashers,
+        )
+    }
+
+    fn with_hashers(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        window: Duration,
+        window_error: f64,
+        hashers: [FastHasher; 2],
+    ) -> Result<Self, CmsError> {
+        if !(window_error > 0.0 && window_error < 1.0) {
+            return Err(CmsError::WindowError(window_error));
+        }
+        let (width, k_num) = CountMinSketch64::<K>::dimensions(capacity, probability, tolerance)?;
+        let max_per_size = (1.0 / window_error).ceil() as usize / 2 + 1;
+        Ok(EcmSketch {
+            cells: vec![ExpHistogram::default(); width * k_num],
+            hashers,
+            mask: width - 1,
+            k_num,
+            window,
+            max_per_size,
+            phantom_k: PhantomData,
+        })
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Records `value` events for `key` at `clock.now()`.
+    pub fn add<Q, T>(&mut self, key: &Q, value: u64, clock: T)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        let now = clock.now();
+        let hashes = key_hashes(&self.hashers, key);
+        for k_i in 0..self.k_num {
+            let cell = &mut self.cells[k_i * (self.mask + 1) + row_offset(&hashes, k_i, self.mask)];
+            cell.expire(now, self.window);
+            cell.insert(now, value, self.max_per_size);
+        }
+    }
+
+    pub fn increment<Q, T>(&mut self, key: &Q, clock: T)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        self.add(key, 1, clock)
+    }
+
+    /// Estimated number of events for `key` during the window ending at `clock.now()`.
+    pub fn estimate<Q, T>(&self, key: &Q, clock: T) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        let now = clock.now();
+        let hashes = key_hashes(&self.hashers, key);
+        (0..self.k_num)
+            .map(|k_i| {
+                let cell = &self.cells[k_i * (self.mask + 1) + row_offset(&hashes, k_i, self.mask)];
+                cell.count(now, self.window)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Drops the expired buckets of every cell.
+    pub fn expire<T>(&mut self, clock: T)
+    where
+        T: Clock,
+    {
+        let now = clock.now();
+        for cell in &mut self.cells {
+            cell.expire(now, self.window);
+        }
+    }
+}
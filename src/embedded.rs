@@ -0,0 +1,155 @@
+use core::borrow::Borrow;
+use core::convert::TryFrom;
+use core::hash::Hash;
+use core::marker::PhantomData;
+use core::time::Duration;
+
+use crate::config::CONFIG_LEN;
+use crate::{
+    key_hashes, row_offset, u64_at, CountMinSketch64, FastHasher, SketchConfig, SKETCH_HEADER_LEN,
+    SKETCH_MAGIC, SKETCH_VERSION,
+};
+
+/// Sketch answering queries straight from serialized bytes, typically an
+/// `include_bytes!()` blob produced by `to_bytes()`, without decoding them.
+///
+/// The bytes can also be a memory-mapped file, so that several processes
+/// query one sketch through the page cache, each holding a single copy.
+/// Sketches with 8-bit counters map the smallest files.
+///
+/// Only the header is parsed on construction. The first update copies the
+/// counters into an owned `CountMinSketch64`, whatever their original width;
+/// the ceiling is kept, so counters still saturate as they originally would.
+pub struct EmbeddedSketch<'a, K> {
+    config: SketchConfig,
+    hashers: [FastHasher; 2],
+    mask: usize,
+    k_num: usize,
+    total: u64,
+    updated_at: Duration,
+    counters: &'a [u8],
+    owned: Option<CountMinSketch64<K>>,
+    phantom_k: PhantomData<K>,
+}
+
+impl<'a, K> EmbeddedSketch<'a, K>
+where
+    K: Hash,
+{
+    pub fn new(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        if bytes.len() < SKETCH_HEADER_LEN || bytes[..4] != SKETCH_MAGIC {
+            return Err("Not a serialized sketch");
+        }
+        if bytes[4] != SKETCH_VERSION {
+            return Err("Unsupported sketch version");
+        }
+        let config = SketchConfig::from_bytes(&bytes[5..5 + CONFIG_LEN])?;
+        if ![1, 2, 4, 8].contains(&config.counter_size) {
+            return Err("Unsupported counter size");
+        }
+        let width = usize::try_from(config.width).map_err(|_| "Width is too large")?;
+        let k_num = usize::try_from(config.depth).map_err(|_| "Depth is too large")?;
+        CountMinSketch64::<K>::check_dimensions(width, k_num)
+            .map_err(|_| "Invalid sketch dimensions")?;
+        let counters = &bytes[SKETCH_HEADER_LEN..];
+        if counters.len() != width * k_num * config.counter_size as usize {
+            return Err("Serialized counters have an unexpected length");
+        }
+        let hashers = [
+            FastHasher::new_with_keys(config.seeds[0], config.seeds[1]),
+            FastHasher::new_with_keys(config.seeds[2], config.seeds[3]),
+        ];
+        Ok(EmbeddedSketch {
+            config,
+            hashers,
+            mask: width - 1,
+            k_num,
+            total: u64_at(bytes, 5 + CONFIG_LEN),
+            updated_at: Duration::from_nanos(u64_at(bytes, 5 + CONFIG_LEN + 16)),
+            counters,
+            owned: None,
+            phantom_k: PhantomData,
+        })
+    }
+
+    /// Returns `true` once an update has copied the counters.
+    pub fn is_owned(&self) -> bool {
+        self.owned.is_some()
+    }
+
+    pub fn config(&self) -> SketchConfig {
+        self.config
+    }
+
+    pub fn total(&self) -> u64 {
+        match &self.owned {
+            Some(cms) => cms.total(),
+            None => self.total,
+        }
+    }
+
+    pub fn estimate<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        if let Some(cms) = &self.owned {
+            return cms.estimate(key);
+        }
+        let hashes = key_hashes(&self.hashers, key);
+        (0..self.k_num)
+            .map(|k_i| self.counter_at(k_i * (self.mask + 1) + row_offset(&hashes, k_i, self.mask)))
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn add<Q>(&mut self, key: &Q, value: u64)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.to_mut().add(key, value)
+    }
+
+    pub fn increment<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.add(key, 1)
+    }
+
+    /// Copies the counters, if that hasn't been done yet.
+    pub fn to_mut(&mut self) -> &mut CountMinSketch64<K> {
+        if self.owned.is_none() {
+            self.owned = Some(self.decode());
+        }
+        self.owned.as_mut().unwrap()
+    }
+
+    pub fn into_sketch(mut self) -> CountMinSketch64<K> {
+        self.owned.take().unwrap_or_else(|| self.decode())
+    }
+
+    fn decode(&self) -> CountMinSketch64<K> {
+        let config = SketchConfig {
+            counter_size: 8,
+            ..self.config
+        };
+        let mut cms = CountMinSketch64::from_config(&config)
+            .expect("Configuration was validated on construction");
+        for (idx, counter) in cms.counters.as_mut_slice().iter_mut().enumerate() {
+            *counter = self.counter_at(idx);
+        }
+        cms.total = self.total;
+        cms.updated_at = self.updated_at;
+        cms
+    }
+
+    fn counter_at(&self, idx: usize) -> u64 {
+        let size = self.config.counter_size as usize;
+        let mut le = [0u8; 8];
+        le[..size].copy_from_slice(&self.counters[idx * size..(idx + 1) * size]);
+        u64::from_le_bytes(le)
+    }
+}
@@ -0,0 +1,117 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::{key_hashes, row_offset, Clock, CmsError, CountMinSketch64, FastHasher};
+
+/// Counter decaying by `exp(-λ·Δt)`, as of the time it was last touched.
+#[derive(Clone, Copy, Debug, Default)]
+struct DecayedCell {
+    value: f64,
+    updated_at: Duration,
+}
+
+impl DecayedCell {
+    fn value_at(&self, now: Duration, lambda: f64) -> f64 {
+        let elapsed = now.saturating_sub(self.updated_at).as_secs_f64();
+        self.value * (-lambda * elapsed).exp()
+    }
+}
+
+/// Sketch whose counters decay exponentially with time, so that recent
+/// updates weigh more than old ones.
+///
+/// Each cell remembers when it was last updated and is decayed lazily, only
+/// when touched, so aging needs no pass over the sketch. Timestamps going
+/// backwards are treated as no time elapsed.
+pub struct ExpDecaySketch<K> {
+    cells: Vec<DecayedCell>,
+    hashers: [FastHasher; 2],
+    mask: usize,
+    k_num: usize,
+    lambda: f64,
+    phantom_k: PhantomData<K>,
+}
+
+impl<K> ExpDecaySketch<K>
+where
+    K: Hash,
+{
+    /// `lambda` is the decay rate per second: a weight is divided by `e`
+    /// every `1 / lambda` seconds, and halved every `ln(2) / lambda`.
+    pub fn new(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        lambda: f64,
+    ) -> Result<Self, CmsError> {
+        if !(lambda >= 0.0 && lambda.is_finite()) {
+            return Err(CmsError::DecayRate(lambda));
+        }
+        let (width, k_num) = CountMinSketch64::<K>::dimensions(capacity, probability, tolerance)?;
+        Ok(ExpDecaySketch {
+            cells: vec![DecayedCell::default(); width * k_num],
+            hashers: [
+                CountMinSketch64::<K>::sip_new(),
+                CountMinSketch64::<K>::sip_new(),
+            ],
+            mask: width - 1,
+            k_num,
+            lambda,
+            phantom_k: PhantomData,
+        })
+    }
+
+    pub fn lambda(&self) -> f64 {
+        self.lambda
+    }
+
+    /// Adds `value` to the count of `key` at `clock.now()`.
+    pub fn add<Q, T>(&mut self, key: &Q, value: f64, clock: T)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        let now = clock.now();
+        let hashes = key_hashes(&self.hashers, key);
+        for k_i in 0..self.k_num {
+            let cell = &mut self.cells[k_i * (self.mask + 1) + row_offset(&hashes, k_i, self.mask)];
+            cell.value = cell.value_at(now, self.lambda) + value;
+            cell.updated_at = cell.updated_at.max(now);
+        }
+    }
+
+    pub fn increment<Q, T>(&mut self, key: &Q, clock: T)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        self.add(key, 1.0, clock)
+    }
+
+    /// Decayed count of `key` as of `clock.now()`.
+    pub fn estimate<Q, T>(&self, key: &Q, clock: T) -> f64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        let now = clock.now();
+        let hashes = key_hashes(&self.hashers, key);
+        (0..self.k_num)
+            .map(|k_i| {
+                let cell = &self.cells[k_i * (self.mask + 1) + row_offset(&hashes, k_i, self.mask)];
+                cell.value_at(now, self.lambda)
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn clear(&mut self) {
+        for cell in &mut self.cells {
+            *cell = DecayedCell::default();
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+
+pub const FLOW_KEY_LEN: usize = 38;
+
+/// Network 5-tuple, hashed as a single packed byte string rather than
+/// field by field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlowKey {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+}
+
+impl FlowKey {
+    pub fn new(src: IpAddr, dst: IpAddr, src_port: u16, dst_port: u16, protocol: u8) -> Self {
+        FlowKey {
+            src,
+            dst,
+            src_port,
+            dst_port,
+            protocol,
+        }
+    }
+
+    /// Layout: family (4 or 6, taken from `src`), source and destination
+    /// addresses as 16 bytes each (IPv4 mapped to IPv6), big-endian ports,
+    /// protocol.
+    pub fn to_bytes(&self) -> [u8; FLOW_KEY_LEN] {
+        let mut packed = [0u8; FLOW_KEY_LEN];
+        packed[0] = match self.src {
+            IpAddr::V4(_) => 4,
+            IpAddr::V6(_) => 6,
+        };
+        packed[1..17].copy_from_slice(&Self::ip_octets(self.src));
+        packed[17..33].copy_from_slice(&Self::ip_octets(self.dst));
+        packed[33..35].copy_from_slice(&self.src_port.to_be_bytes());
+        packed[35..37].copy_from_slice(&self.dst_port.to_be_bytes());
+        packed[37] = self.protocol;
+        packed
+    }
+
+    fn ip_octets(ip: IpAddr) -> [u8; 16] {
+        match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+            IpAddr::V6(ip) => ip.octets(),
+        }
+    }
+}
+
+impl Hash for FlowKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write(&self.to_bytes());
+    }
+}
@@ -0,0 +1,104 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use crate::{CmsError, CountMinSketch64};
+
+/// Counting facade over a sketch, with the method names of a
+/// `HashMap<K, u64>` used for counting, to ease migrating such code.
+///
+/// Counts are estimates: they may be too high, never too low.
+pub struct FrequencyCounter<K> {
+    cms: CountMinSketch64<K>,
+}
+
+impl<K> FrequencyCounter<K>
+where
+    K: Hash,
+{
+    pub fn new(capacity: usize, probability: f64, tolerance: f64) -> Result<Self, CmsError> {
+        Ok(FrequencyCounter {
+            cms: CountMinSketch64::new(capacity, probability, tolerance)?,
+        })
+    }
+
+    pub fn from_sketch(cms: CountMinSketch64<K>) -> Self {
+        FrequencyCounter { cms }
+    }
+
+    /// Counts one more occurrence of `key`, like
+    /// `*map.entry(key).or_insert(0) += 1`, and returns its count.
+    pub fn count<Q>(&mut self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.cms.increment_and_estimate(key)
+    }
+
+    /// Counts `value` more occurrences of `key`, and returns its count.
+    pub fn count_by<Q>(&mut self, key: &Q, value: u64) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.cms.add_and_estimate(key, value)
+    }
+
+    /// Count of `key`, `None` if it was never counted (or only collides
+    /// with keys that weren't).
+    pub fn get<Q>(&self, key: &Q) -> Option<u64>
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        Some(self.cms.estimate(key)).filter(|&count| count > 0)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Estimated number of distinct keys, from the fraction of counters
+    /// still at zero in each row (linear counting).
+    ///
+    /// Once a row has no zero counter left, it counts as having a single
+    /// one, so estimates stop growing past about `width * ln(width)`.
+    pub fn len_estimate(&self) -> usize {
+        let width = self.cms.width() as f64;
+        let sum: f64 = self
+            .cms
+            .rows()
+            .map(|row| {
+                let zeros = row.iter().filter(|&&counter| counter == 0).count();
+                -width * (zeros.max(1) as f64 / width).ln()
+            })
+            .sum();
+        (sum / self.cms.depth() as f64).round() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cms.total() == 0
+    }
+
+    /// Sum of all the counts.
+    pub fn total(&self) -> u64 {
+        self.cms.total()
+    }
+
+    /// Forgets all the counts, keeping the hash keys.
+    pub fn clear(&mut self) {
+        self.cms.clear_keep_seeds();
+    }
+
+    pub fn sketch(&self) -> &CountMinSketch64<K> {
+        &self.cms
+    }
+
+    pub fn into_sketch(self) -> CountMinSketch64<K> {
+        self.cms
+    }
+}
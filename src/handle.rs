@@ -0,0 +1,130 @@
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::CountMinSketch64;
+
+enum Message<K> {
+    Add(K, u64),
+    Estimate(K, Arc<Reply>),
+}
+
+#[derive(Default)]
+struct Reply {
+    state: Mutex<ReplyState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct ReplyState {
+    value: Option<Option<u64>>,
+    waker: Option<Waker>,
+}
+
+impl Reply {
+    fn complete(&self, value: Option<u64>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.value = Some(value);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+/// Cheaply cloneable handle to a sketch owned by a dedicated worker thread.
+///
+/// Updates are sent over a channel and applied in order; the worker exits
+/// once every handle has been dropped.
+pub struct SketchHandle<K> {
+    sender: Sender<Message<K>>,
+}
+
+impl<K> Clone for SketchHandle<K> {
+    fn clone(&self) -> Self {
+        SketchHandle {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<K> SketchHandle<K>
+where
+    K: Hash + Send + 'static,
+{
+    pub fn spawn(mut cms: CountMinSketch64<K>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for message in receiver {
+                match message {
+                    Message::Add(key, value) => cms.add(&key, value),
+                    Message::Estimate(key, reply) => reply.complete(Some(cms.estimate(&key))),
+                }
+            }
+        });
+        SketchHandle { sender }
+    }
+
+    pub fn add(&self, key: K, value: u64) {
+        let _ = self.sender.send(Message::Add(key, value));
+    }
+
+    pub fn increment(&self, key: K) {
+        self.add(key, 1)
+    }
+
+    /// Queries the worker; the estimate reflects all the updates sent
+    /// through this handle before the query.
+    pub fn estimate(&self, key: K) -> Estimate {
+        let reply = Arc::new(Reply::default());
+        if self
+            .sender
+            .send(Message::Estimate(key, reply.clone()))
+            .is_err()
+        {
+            reply.complete(None);
+        }
+        Estimate { reply }
+    }
+}
+
+/// Pending estimate, resolving to `None` if the worker is gone.
+pub struct Estimate {
+    reply: Arc<Reply>,
+}
+
+impl Estimate {
+    /// Blocks the current thread until the estimate is available.
+    pub fn wait(self) -> Option<u64> {
+        let mut state = self.reply.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(value) = state.value {
+                return value;
+            }
+            state = self
+                .reply
+                .ready
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Future for Estimate {
+    type Output = Option<u64>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.reply.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.value {
+            Some(value) => Poll::Ready(value),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+// Larger caches would rarely fit in the CPU caches anyway
+const MAX_CAPACITY: usize = 1 << 16;
+
+/// Direct-mapped cache of the hash pairs of recently used keys.
+///
+/// A key can only live in the slot selected by a cheap hash of it, and
+/// replaces whatever key was there, so lookups and insertions are a single
+/// comparison. The capacity is rounded up to a power of two, and capped at
+/// 65536 slots.
+pub(crate) struct HashCache<K> {
+    slots: Vec<Option<(K, [u64; 2])>>,
+    capacity: usize,
+}
+
+impl<K> HashCache<K> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let slots = match capacity {
+            0 => 0,
+            _ => capacity.min(MAX_CAPACITY).next_power_of_two(),
+        };
+        HashCache {
+            slots: (0..slots).map(|_| None).collect(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+    }
+}
+
+impl<K> HashCache<K>
+where
+    K: Hash + Eq,
+{
+    pub(crate) fn get(&self, key: &K) -> Option<[u64; 2]> {
+        match self.slots.get(self.slot(key))? {
+            Some((k, hashes)) if k == key => Some(*hashes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: K, hashes: [u64; 2]) {
+        let slot = self.slot(&key);
+        if let Some(entry) = self.slots.get_mut(slot) {
+            *entry = Some((key, hashes));
+        }
+    }
+
+    #[inline]
+    fn slot(&self, key: &K) -> usize {
+        let mut hasher = MixHasher(0);
+        key.hash(&mut hasher);
+        hasher.finish() as usize & self.slots.len().wrapping_sub(1)
+    }
+}
+
+// Multiply-rotate hash, only used to pick a slot
+struct MixHasher(u64);
+
+impl Hasher for MixHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+
+    #[inline]
+    fn write_u64(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        // The high bits are the best mixed
+        self.0.rotate_left(32)
+    }
+}
@@ -0,0 +1,47 @@
+use core::hash::BuildHasher;
+
+use crate::FastHasher;
+
+/// Builds SipHash-1-3 hashers with fixed keys.
+///
+/// This is the default hasher, and the only one whose keys are part of
+/// `SketchConfig` and of the serialization formats.
+#[derive(Clone, Copy, Debug)]
+pub struct SipBuildHasher {
+    hasher: FastHasher,
+}
+
+impl SipBuildHasher {
+    pub fn new_with_keys(k0: u64, k1: u64) -> Self {
+        SipBuildHasher {
+            hasher: FastHasher::new_with_keys(k0, k1),
+        }
+    }
+
+    pub fn keys(&self) -> (u64, u64) {
+        self.hasher.keys()
+    }
+}
+
+impl From<FastHasher> for SipBuildHasher {
+    fn from(hasher: FastHasher) -> Self {
+        SipBuildHasher { hasher }
+    }
+}
+
+impl BuildHasher for SipBuildHasher {
+    type Hasher = FastHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> FastHasher {
+        self.hasher
+    }
+}
+
+impl PartialEq for SipBuildHasher {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys() == other.keys()
+    }
+}
+
+impl Eq for SipBuildHasher {}
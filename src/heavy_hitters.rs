@@ -0,0 +1,55 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+/// Keys whose estimate reached `threshold` when they were added, each
+/// recorded once. Keys are deduplicated by their hash pair, so `K` doesn't
+/// need to be comparable.
+pub(crate) struct HeavyHitters<K, C> {
+    threshold: C,
+    keys: Vec<K>,
+    seen: BTreeSet<[u64; 2]>,
+}
+
+impl<K, C> HeavyHitters<K, C>
+where
+    C: Copy + Ord,
+{
+    pub(crate) fn new(threshold: C) -> Self {
+        HeavyHitters {
+            threshold,
+            keys: Vec::new(),
+            seen: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn threshold(&self) -> C {
+        self.threshold
+    }
+
+    /// Same keys, compared against `threshold` from now on.
+    pub(crate) fn with_threshold<C2>(self, threshold: C2) -> HeavyHitters<K, C2> {
+        HeavyHitters {
+            threshold,
+            keys: self.keys,
+            seen: self.seen,
+        }
+    }
+
+    pub(crate) fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.keys.clear();
+        self.seen.clear();
+    }
+
+    pub(crate) fn offer(&mut self, key: &K, hashes: [u64; 2], estimate: C)
+    where
+        K: Clone,
+    {
+        if estimate >= self.threshold && self.seen.insert(hashes) {
+            self.keys.push(key.clone());
+        }
+    }
+}
@@ -1,229 +1,5353 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::{max, min, Ordering};
+use core::convert::TryFrom;
+use core::fmt;
+use core::hash::{BuildHasher, Hash, Hasher};
+#[cfg(feature = "std")]
+use core::iter::FromIterator;
 use rand::RngCore;
-use std::borrow::Borrow;
-use std::cmp::max;
-use std::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
+use std::io::Read;
 
 use siphasher::sip::SipHasher13;
+use siphasher::sip128::Hasher128;
 type FastHasher = SipHasher13;
 
-use std::marker::PhantomData;
-use std::mem;
+use core::marker::PhantomData;
+use core::mem;
+use core::time::Duration;
+
+#[cfg(feature = "std")]
+mod admission;
+mod atomic;
+mod base64;
+mod blocked;
+mod builder;
+mod clock;
+mod config;
+mod count_sketch;
+mod counter;
+mod crdt;
+mod critical_section;
+#[cfg(feature = "std")]
+mod decayed_topk;
+#[cfg(feature = "std")]
+mod ecm;
+mod embedded;
+#[cfg(feature = "std")]
+mod exp_decay;
+#[cfg(feature = "std")]
+mod flow;
+#[cfg(feature = "std")]
+mod frequency;
+#[cfg(feature = "std")]
+mod handle;
+mod hash_cache;
+mod hasher;
+mod heavy_hitters;
+mod matrix;
+mod normalize;
+#[cfg(feature = "std")]
+mod quantize;
+#[cfg(feature = "std")]
+mod reservoir;
+mod shared;
+mod simd;
+#[cfg(feature = "std")]
+mod simulate;
+#[cfg(feature = "std")]
+mod sketch2d;
+#[cfg(feature = "std")]
+mod sliding;
+#[cfg(feature = "std")]
+mod temporal;
+#[cfg(feature = "std")]
+mod topk;
+mod untyped;
+#[cfg(feature = "std")]
+mod windowed;
+
+use clock::SharedClock;
+use config::CONFIG_LEN;
+use hash_cache::HashCache;
+use heavy_hitters::HeavyHitters;
+#[cfg(feature = "std")]
+use quantize::LogQuantizer;
+#[cfg(feature = "std")]
+use reservoir::Reservoir;
+
+#[cfg(feature = "std")]
+pub use admission::TinyLfuAdmission;
+pub use atomic::{
+    AtomicCountMinSketch, AtomicCountMinSketch32, AtomicCountMinSketch64, AtomicCounter,
+};
+pub use blocked::BlockedCountMinSketch;
+pub use builder::CountMinSketchBuilder;
+#[cfg(feature = "std")]
+pub use clock::SystemClock;
+pub use clock::{Clock, ManualClock};
+pub use config::SketchConfig;
+pub use count_sketch::CountSketch;
+pub use counter::Counter;
+pub use crdt::{CrdtError, CrdtSketch, ReplicaState};
+pub use critical_section::{CriticalSection, CriticalSectionSketch};
+#[cfg(feature = "std")]
+pub use decayed_topk::DecayedTopK;
+#[cfg(feature = "std")]
+pub use ecm::EcmSketch;
+pub use embedded::EmbeddedSketch;
+#[cfg(feature = "std")]
+pub use exp_decay::ExpDecaySketch;
+#[cfg(feature = "std")]
+pub use flow::{FlowKey, FLOW_KEY_LEN};
+#[cfg(feature = "std")]
+pub use frequency::FrequencyCounter;
+#[cfg(feature = "std")]
+pub use handle::{Estimate, SketchHandle};
+pub use hasher::SipBuildHasher;
+pub use matrix::CounterMatrix;
+pub use normalize::{KeyNormalizer, Lowercase, NoNormalizer, NormalizeKey, Trim};
+pub use shared::SharedCountMinSketch;
+#[cfg(feature = "std")]
+pub use simulate::{simulate_accuracy, AccuracyReport, SketchParams, StreamModel};
+#[cfg(feature = "std")]
+pub use sketch2d::CountMinSketch2D;
+#[cfg(feature = "std")]
+pub use sliding::SlidingCountMinSketch;
+#[cfg(feature = "std")]
+pub use temporal::TemporalSketch;
+#[cfg(feature = "std")]
+pub use topk::TopK;
+pub use untyped::BytesCountMinSketch;
+#[cfg(feature = "std")]
+pub use windowed::WindowedCountMin;
+
+/// Backing store of the counters, as `depth` rows of `width` counters.
+///
+/// Implementations over memory that outlives the process, such as a
+/// file-backed mapping, are opened with `CountMinSketch::from_storage()`.
+pub trait Storage<C> {
+    fn row(&self, k_i: usize) -> &[C];
+    fn row_mut(&mut self, k_i: usize) -> &mut [C];
+    /// Number of rows.
+    fn depth(&self) -> usize;
+}
+
+impl<C> Storage<C> for Vec<Vec<C>> {
+    #[inline]
+    fn depth(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn row(&self, k_i: usize) -> &[C] {
+        &self[k_i]
+    }
+
+    #[inline]
+    fn row_mut(&mut self, k_i: usize) -> &mut [C] {
+        &mut self[k_i]
+    }
+}
+
+/// Counters stored in a single allocation, row after row.
+pub struct VecStorage<C> {
+    counters: Vec<C>,
+    width: usize,
+}
 
-macro_rules! cms_define {
-    ($CountMinSketch:ident, $Counter:ty) => {
-        pub struct $CountMinSketch<K> {
-            counters: Vec<Vec<$Counter>>,
-            offsets: Vec<usize>,
-            hashers: [FastHasher; 2],
-            mask: usize,
-            k_num: usize,
-            reset_idx: usize,
-            phantom_k: PhantomData<K>,
+impl<C> VecStorage<C>
+where
+    C: Counter,
+{
+    fn new(width: usize, depth: usize) -> Self {
+        VecStorage {
+            counters: vec![C::ZERO; width * depth],
+            width,
         }
+    }
+}
 
-        impl<K> $CountMinSketch<K>
-        where
-            K: Hash,
-        {
-            pub fn new(
-                capacity: usize,
-                probability: f64,
-                tolerance: f64,
-            ) -> Result<Self, &'static str> {
-                let width = Self::optimal_width(capacity, tolerance);
-                let k_num = Self::optimal_k_num(probability);
-                let counters: Vec<Vec<$Counter>> = vec![vec![0; width]; k_num];
-                let offsets = vec![0; k_num];
-                let hashers = [Self::sip_new(), Self::sip_new()];
-                let cms = $CountMinSketch {
-                    counters,
-                    offsets,
-                    hashers,
-                    mask: Self::mask(width),
-                    k_num,
-                    reset_idx: 0,
-                    phantom_k: PhantomData,
-                };
-                Ok(cms)
+impl<C> VecStorage<C> {
+    #[inline]
+    fn as_slice(&self) -> &[C] {
+        &self.counters
+    }
+
+    #[inline]
+    fn as_mut_slice(&mut self) -> &mut [C] {
+        &mut self.counters
+    }
+}
+
+impl<C> Storage<C> for VecStorage<C> {
+    #[inline]
+    fn depth(&self) -> usize {
+        self.counters.len() / self.width
+    }
+
+    #[inline]
+    fn row(&self, k_i: usize) -> &[C] {
+        &self.counters[k_i * self.width..(k_i + 1) * self.width]
+    }
+
+    #[inline]
+    fn row_mut(&mut self, k_i: usize) -> &mut [C] {
+        &mut self.counters[k_i * self.width..(k_i + 1) * self.width]
+    }
+}
+
+/// Counters stored in a caller-provided buffer, row after row.
+pub struct SliceStorage<'a, C> {
+    counters: &'a mut [C],
+    width: usize,
+}
+
+impl<'a, C> SliceStorage<'a, C> {
+    /// Rows of `width` counters from `counters`, such as a memory-mapped
+    /// file. A trailing partial row is ignored.
+    pub fn new(counters: &'a mut [C], width: usize) -> Result<Self, CmsError> {
+        if width == 0 {
+            return Err(CmsError::Width(0));
+        }
+        Ok(SliceStorage { counters, width })
+    }
+}
+
+impl<'a, C> Storage<C> for SliceStorage<'a, C> {
+    #[inline]
+    fn depth(&self) -> usize {
+        self.counters.len() / self.width
+    }
+
+    #[inline]
+    fn row(&self, k_i: usize) -> &[C] {
+        &self.counters[k_i * self.width..(k_i + 1) * self.width]
+    }
+
+    #[inline]
+    fn row_mut(&mut self, k_i: usize) -> &mut [C] {
+        &mut self.counters[k_i * self.width..(k_i + 1) * self.width]
+    }
+}
+
+/// What `add()` does when a counter would go past the ceiling.
+///
+/// There is no policy promoting counters to a wider type, which is fixed at
+/// compile time; convert the sketch with `From` instead, for example
+/// `CountMinSketch16::from(cms8)`.
+///
+/// `try_add()` reports overflows whatever the policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Counters stop at the ceiling (the default).
+    Saturate,
+    /// The whole update is rejected, leaving the counters and the total
+    /// unchanged. Rejections are counted by `rejected_updates()`, reported
+    /// to the handler set with `set_overflow_handler()`, and don't count
+    /// as inserts for `DecayPolicy::EveryNInserts`.
+    Error,
+    /// Counters wrap around past the maximum of the counter type, ignoring
+    /// the ceiling. Every row is incremented, as without conservative
+    /// update, and wrapped counters make estimates too low.
+    Wrap,
+}
+
+/// How `reset()`, `reset_next()` and `decay()` round decayed counters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rounding {
+    /// Truncates, so counts of 1 are cleared by a single pass.
+    Down,
+    /// Rounds to the nearest count, halves up. Halving never brings a
+    /// nonzero count to 0, but `decay()` with a small factor can.
+    HalfUp,
+    /// Truncates, but leaves a count of 1 in cells that were nonzero.
+    KeepNonZero,
+}
+
+impl Rounding {
+    #[inline]
+    fn halve(self, value: u64) -> u64 {
+        match self {
+            Rounding::Down => value / 2,
+            Rounding::HalfUp => value / 2 + (value & 1),
+            Rounding::KeepNonZero => max(value / 2, min(value, 1)),
+        }
+    }
+
+    // `factor` is in [0, 1]
+    #[inline]
+    fn scale(self, value: u64, factor: f64) -> u64 {
+        let scaled = value as f64 * factor;
+        match self {
+            Rounding::Down => scaled as u64,
+            Rounding::HalfUp => (scaled + 0.5) as u64,
+            Rounding::KeepNonZero => max(scaled as u64, min(value, 1)),
+        }
+    }
+}
+
+/// When a sketch ages itself by halving its counters with `reset()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DecayPolicy {
+    /// Only explicit calls age the sketch.
+    Never,
+    /// After every `n` updates, counting `add()` calls rather than values.
+    EveryNInserts(u64),
+    /// Every period of that duration, as measured by the clock given to
+    /// `set_decay_policy()` or to the builder, on every update. `add_at()`
+    /// also ages the sketch up to the time it is given.
+    EveryDuration(Duration),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CounterType {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+/// Recommends the narrowest counter type that won't saturate for a stream of
+/// `expected_total_weight` in which the heaviest key accounts for
+/// `expected_max_key_share` (0.0 to 1.0) of the weight.
+///
+/// A 2x headroom is kept for collision noise.
+#[cfg(feature = "std")]
+pub fn recommend_counter_type(
+    expected_total_weight: u64,
+    expected_max_key_share: f64,
+) -> CounterType {
+    let share = if expected_max_key_share.is_nan() {
+        1.0
+    } else {
+        expected_max_key_share.clamp(0.0, 1.0)
+    };
+    let peak = (expected_total_weight as f64 * share).ceil() * 2.0;
+    if peak <= u8::MAX as f64 {
+        CounterType::U8
+    } else if peak <= u16::MAX as f64 {
+        CounterType::U16
+    } else if peak <= u32::MAX as f64 {
+        CounterType::U32
+    } else {
+        CounterType::U64
+    }
+}
+
+/// Counter values of a key across rows. The estimate is `min`; the further
+/// apart the other rows are, the more collisions affect the key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spread<C> {
+    pub min: C,
+    pub median: C,
+    pub max: C,
+    /// `(max - min) / max`, from 0.0 (all rows agree) to 1.0.
+    pub dispersion: f64,
+}
+
+/// Estimates of a key in two snapshots of a sketch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delta<C> {
+    pub current: C,
+    pub previous: C,
+    /// `current - previous`, negative if the key decayed.
+    pub change: i128,
+}
+
+/// Summary of the state of a sketch, from `stats()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats<C> {
+    pub width: usize,
+    pub depth: usize,
+    /// Sum of all the updates, as with `total()`.
+    pub total: u64,
+    /// Fraction of the counters that are not zero.
+    pub occupancy: f64,
+    pub max: C,
+    pub mean: f64,
+    /// Bound on the overestimation of a key, as with
+    /// `estimate_with_bound()`.
+    pub error_bound: u64,
+}
+
+/// Copy of the counters of a sketch, from `snapshot()`, to later encode
+/// only the counters that changed with `delta_since()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<C> {
+    counters: Vec<C>,
+    width: usize,
+    depth: usize,
+    digest: u128,
+}
+
+/// Hashes of a key, from `hash_key()`, to update or query sketches sharing
+/// the same hashers and normalizer without hashing the key again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HashedKey {
+    hashes: [u64; 2],
+}
+
+/// Why a sketch couldn't be created, updated or queried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CmsError {
+    /// The capacity must be at least 1.
+    Capacity(usize),
+    /// The probability must be in `(0, 1)`.
+    Probability(f64),
+    /// The tolerance must be finite and positive.
+    Tolerance(f64),
+    /// The width must be a power of two, at least 2.
+    Width(u64),
+    /// The depth must be at least 1.
+    Depth(u64),
+    /// The counters would not fit in memory.
+    TooLarge,
+    /// The configuration is for counters of another size, in bytes.
+    CounterSize(u8),
+    /// The ceiling doesn't fit in the counter type.
+    Ceiling(u64),
+    /// The buffer must hold at least `needed` counters, or bytes when
+    /// serializing.
+    BufferTooSmall { needed: usize },
+    /// Builder settings are missing or contradict each other.
+    Builder(&'static str),
+    /// Removals require the standard update.
+    ConservativeUpdate,
+    /// Decay factors must be in `[0, 1]`.
+    DecayFactor(f64),
+    /// Sliding windows must have at least one slot.
+    Slots(usize),
+    /// Decay rates must be finite and non-negative.
+    DecayRate(f64),
+    /// Half-lives and epochs must not be zero.
+    ZeroDuration,
+    /// The relative error of a window must be in `(0, 1)`.
+    WindowError(f64),
+    /// Simulated streams must have at least one key.
+    DistinctKeys(usize),
+    /// Zipf exponents must be finite and non-negative.
+    ZipfExponent(f64),
+    /// The conditioning key has not been observed.
+    Unobserved,
+    /// Counts are below the minimum for a reliable estimate.
+    CountTooLow,
+}
+
+impl fmt::Display for CmsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmsError::Capacity(capacity) => write!(f, "Invalid capacity ({})", capacity),
+            CmsError::Probability(probability) => {
+                write!(f, "Probability must be in (0, 1), got {}", probability)
+            }
+            CmsError::Tolerance(tolerance) => {
+                write!(f, "Tolerance must be positive, got {}", tolerance)
+            }
+            CmsError::Width(width) => write!(f, "Width must be a power of two, got {}", width),
+            CmsError::Depth(depth) => write!(f, "Invalid depth ({})", depth),
+            CmsError::TooLarge => write!(f, "Sketch would be way too large"),
+            CmsError::CounterSize(size) => {
+                write!(f, "Configuration is for {}-byte counters", size)
+            }
+            CmsError::Ceiling(ceiling) => {
+                write!(f, "Ceiling {} is too large for the counter type", ceiling)
+            }
+            CmsError::BufferTooSmall { needed } => {
+                write!(f, "Buffer is too small ({} needed)", needed)
             }
+            CmsError::Builder(reason) => write!(f, "{}", reason),
+            CmsError::ConservativeUpdate => {
+                write!(f, "Removals require conservative update to be disabled")
+            }
+            CmsError::DecayFactor(factor) => {
+                write!(f, "Decay factor must be in [0, 1], got {}", factor)
+            }
+            CmsError::Slots(slots) => write!(f, "Invalid number of slots ({})", slots),
+            CmsError::DecayRate(lambda) => {
+                write!(
+                    f,
+                    "Decay rate must be finite and non-negative, got {}",
+                    lambda
+                )
+            }
+            CmsError::ZeroDuration => write!(f, "Duration must not be zero"),
+            CmsError::WindowError(error) => {
+                write!(f, "Window error must be in (0, 1), got {}", error)
+            }
+            CmsError::DistinctKeys(keys) => write!(f, "Invalid number of keys ({})", keys),
+            CmsError::ZipfExponent(exponent) => {
+                write!(f, "Invalid Zipf exponent ({})", exponent)
+            }
+            CmsError::Unobserved => write!(f, "Conditioning key has not been observed"),
+            CmsError::CountTooLow => write!(f, "Counts are too low for a reliable estimate"),
+        }
+    }
+}
 
-            pub fn add<Q: ?Sized>(&mut self, key: &Q, value: $Counter)
-            where
-                Q: Hash,
-                K: Borrow<Q>,
-            {
-                let mut hashes = [0u64, 0u64];
-                let lowest = (0..self.k_num)
-                    .map(|k_i| {
-                        let offset = self.offset(&mut hashes, key, k_i);
-                        self.offsets[k_i] = offset;
-                        self.counters[k_i][offset]
-                    })
-                    .min()
-                    .unwrap();
-                for k_i in 0..self.k_num {
-                    let offset = self.offsets[k_i];
-                    if self.counters[k_i][offset] == lowest {
-                        self.counters[k_i][offset] =
-                            self.counters[k_i][offset].saturating_add(value);
-                    }
-                }
+#[cfg(feature = "std")]
+impl std::error::Error for CmsError {}
+
+/// Why two sketches can't be combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Incompatibility {
+    Width { ours: usize, theirs: usize },
+    Depth { ours: usize, theirs: usize },
+    Seeds,
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Incompatibility::Width { ours, theirs } => {
+                write!(f, "Width mismatch ({} vs {})", ours, theirs)
+            }
+            Incompatibility::Depth { ours, theirs } => {
+                write!(f, "Depth mismatch ({} vs {})", ours, theirs)
             }
+            Incompatibility::Seeds => write!(f, "Hash keys mismatch"),
+        }
+    }
+}
 
-            pub fn increment<Q: ?Sized>(&mut self, key: &Q)
-            where
-                Q: Hash,
-                K: Borrow<Q>,
-            {
-                self.add(key, 1)
+#[cfg(feature = "std")]
+impl std::error::Error for Incompatibility {}
+
+/// An update would have taken a counter past the ceiling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaturationError {
+    pub ceiling: u64,
+}
+
+impl fmt::Display for SaturationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Counter would exceed the ceiling ({})", self.ceiling)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SaturationError {}
+
+// Number of keys hashed and prefetched ahead of the updates in bulk operations
+const BATCH_LEN: usize = 16;
+
+// Dimensions of the sketches built by `collect()`
+#[cfg(feature = "std")]
+const COLLECT_WIDTH: usize = 4096;
+#[cfg(feature = "std")]
+const COLLECT_DEPTH: usize = 7;
+
+const SKETCH_MAGIC: [u8; 4] = *b"CMSK";
+const SKETCH_VERSION: u8 = 2;
+const SKETCH_HEADER_LEN: usize = 4 + 1 + CONFIG_LEN + 8 * 3;
+#[cfg(feature = "std")]
+const QUANTIZED_MAGIC: [u8; 4] = *b"CMSQ";
+const SPARSE_MAGIC: [u8; 4] = *b"CMSZ";
+const DELTA_MAGIC: [u8; 4] = *b"CMSD";
+const DELTA_VERSION: u8 = 2;
+const DELTA_HEADER_LEN: usize = 4 + 1 + CONFIG_LEN + 16 + 8 + 8;
+
+fn u64_at(bytes: &[u8], pos: usize) -> u64 {
+    let mut le = [0u8; 8];
+    le.copy_from_slice(&bytes[pos..pos + 8]);
+    u64::from_le_bytes(le)
+}
+
+// LEB128
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, &'static str> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or("Truncated varint")?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("Invalid varint")
+}
+
+/// Index of the cell of `hashes` in row `k_i`.
+///
+/// The derivation only uses u64 arithmetic, so that serialized sketches index
+/// the same way on 32-bit and 64-bit targets.
+#[inline]
+fn row_index(hashes: &[u64; 2], k_i: usize, mask: u64) -> u64 {
+    let hash = if k_i < 2 {
+        hashes[k_i]
+    } else {
+        hashes[0].wrapping_add((k_i as u64).wrapping_mul(hashes[1]) % 0xffffffffffffffc5)
+    };
+    hash & mask
+}
+
+#[inline]
+fn row_offset(hashes: &[u64; 2], k_i: usize, mask: usize) -> usize {
+    // The result is at most `mask`, so it always fits
+    row_index(hashes, k_i, mask as u64) as usize
+}
+
+/// The two hashes of `key` that `row_index()` derives all the rows from,
+/// for the sketches that keep a pair of keyed hashers.
+#[inline]
+pub(crate) fn key_hashes<Q>(hashers: &[FastHasher; 2], key: &Q) -> [u64; 2]
+where
+    Q: Hash + ?Sized,
+{
+    let mut hashes = [0u64, 0u64];
+    for (hash, hasher) in hashes.iter_mut().zip(hashers) {
+        let sip = &mut hasher.clone();
+        key.hash(sip);
+        *hash = sip.finish();
+    }
+    hashes
+}
+
+/// Count-min sketch of `K` keys with `C` counters.
+///
+/// `CountMinSketch8` to `CountMinSketch64` are aliases for the built-in
+/// counter widths.
+pub struct CountMinSketch<K, C, S = VecStorage<C>, N = NoNormalizer, H = SipBuildHasher> {
+    counters: S,
+    hashers: [H; 2],
+    mask: usize,
+    k_num: usize,
+    reset_idx: usize,
+    total: u64,
+    updated_at: Duration,
+    ceiling: C,
+    rounding: Rounding,
+    conservative: bool,
+    decay_policy: DecayPolicy,
+    inserts_since_decay: u64,
+    decayed_at: Duration,
+    clock: Option<SharedClock>,
+    overflow_policy: OverflowPolicy,
+    rejected_updates: u64,
+    overflow_handler: Option<fn(SaturationError)>,
+    saturated_updates: u64,
+    pinned: Vec<[u64; 2]>,
+    hash_cache: Option<HashCache<K>>,
+    #[cfg(feature = "std")]
+    reservoir: Option<Reservoir<K>>,
+    heavy_hitters: Option<HeavyHitters<K, C>>,
+    normalizer: N,
+    phantom_k: PhantomData<K>,
+}
+
+impl<K, C> CountMinSketch<K, C>
+where
+    K: Hash,
+    C: Counter,
+{
+    pub fn builder() -> CountMinSketchBuilder<K, C> {
+        CountMinSketchBuilder::new()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn new(capacity: usize, probability: f64, tolerance: f64) -> Result<Self, CmsError> {
+        let (width, k_num) = Self::dimensions(capacity, probability, tolerance)?;
+        let counters = VecStorage::new(width, k_num);
+        let hashers = [Self::sip_new().into(), Self::sip_new().into()];
+        Ok(Self::with_storage(counters, hashers, width, k_num))
+    }
+
+    /// Creates a sketch of `depth` rows of `width` counters, skipping the
+    /// sizing from capacity, probability and tolerance.
+    ///
+    /// The width is rounded up to a power of two (and to at least 2), so
+    /// `config().width` may be larger than requested.
+    #[cfg(feature = "std")]
+    pub fn with_dimensions(width: usize, depth: usize) -> Result<Self, CmsError> {
+        let width = max(2, width)
+            .checked_next_power_of_two()
+            .ok_or(CmsError::TooLarge)?;
+        Self::check_dimensions(width, depth)?;
+        let counters = VecStorage::new(width, depth);
+        let hashers = [Self::sip_new().into(), Self::sip_new().into()];
+        Ok(Self::with_storage(counters, hashers, width, depth))
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`), so that independently built sketches are
+    /// mergeable and reproducible.
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let (width, k_num) = Self::dimensions(capacity, probability, tolerance)?;
+        let counters = VecStorage::new(width, k_num);
+        let hashers = [
+            SipBuildHasher::new_with_keys(seeds[0], seeds[1]),
+            SipBuildHasher::new_with_keys(seeds[2], seeds[3]),
+        ];
+        Ok(Self::with_storage(counters, hashers, width, k_num))
+    }
+
+    /// Same as `new()`, with hash keys drawn from `rng` instead of
+    /// `rand::thread_rng()`.
+    ///
+    /// On targets without a system entropy source, such as
+    /// `wasm32-unknown-unknown`, pass any RNG seeded by the host. `clear()`
+    /// still uses `thread_rng()`; use `clear_keep_seeds()` there instead.
+    pub fn new_with_rng<R>(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        rng: &mut R,
+    ) -> Result<Self, CmsError>
+    where
+        R: RngCore + ?Sized,
+    {
+        let (width, k_num) = Self::dimensions(capacity, probability, tolerance)?;
+        let counters = VecStorage::new(width, k_num);
+        let hashers = [
+            Self::sip_new_with(rng).into(),
+            Self::sip_new_with(rng).into(),
+        ];
+        Ok(Self::with_storage(counters, hashers, width, k_num))
+    }
+
+    pub fn estimate_memory(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+    ) -> Result<usize, CmsError> {
+        let len = Self::buffer_len(capacity, probability, tolerance)?;
+        Ok(len * mem::size_of::<C>())
+    }
+
+    pub fn buffer_len(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+    ) -> Result<usize, CmsError> {
+        let (width, k_num) = Self::dimensions(capacity, probability, tolerance)?;
+        Ok(width * k_num)
+    }
+
+    /// Creates an empty sketch with the dimensions, hash keys and update
+    /// policies of `config`, compatible with any other sketch built from it.
+    pub fn from_config(config: &SketchConfig) -> Result<Self, CmsError> {
+        if config.counter_size != mem::size_of::<C>() as u8 {
+            return Err(CmsError::CounterSize(config.counter_size));
+        }
+        let width = usize::try_from(config.width).map_err(|_| CmsError::TooLarge)?;
+        let k_num = usize::try_from(config.depth).map_err(|_| CmsError::TooLarge)?;
+        Self::check_dimensions(width, k_num)?;
+        let ceiling = C::from_u64(config.ceiling).ok_or(CmsError::Ceiling(config.ceiling))?;
+        let counters = VecStorage::new(width, k_num);
+        let hashers = [
+            SipBuildHasher::new_with_keys(config.seeds[0], config.seeds[1]),
+            SipBuildHasher::new_with_keys(config.seeds[2], config.seeds[3]),
+        ];
+        let mut cms = Self::with_storage(counters, hashers, width, k_num);
+        cms.ceiling = ceiling;
+        cms.apply_policies(config);
+        Ok(cms)
+    }
+
+    /// Rebuilds a sketch from counters previously read with `rows()`, and
+    /// the hash keys of `config().seeds`, for storage layers that keep
+    /// sketches in their own format.
+    ///
+    /// There must be exactly `depth` rows of `width` counters. `total()`
+    /// and `updated_at()` are not part of the counters, and start from
+    /// zero.
+    pub fn from_raw_parts<I, R>(
+        rows: I,
+        seeds: [u64; 4],
+        width: usize,
+        depth: usize,
+    ) -> Result<Self, CmsError>
+    where
+        I: IntoIterator<Item = R>,
+        R: AsRef<[C]>,
+    {
+        Self::check_dimensions(width, depth)?;
+        let mut counters = Vec::with_capacity(width * depth);
+        for row in rows {
+            let row = row.as_ref();
+            if row.len() != width {
+                return Err(CmsError::Width(row.len() as u64));
             }
+            counters.extend_from_slice(row);
+        }
+        if counters.len() != width * depth {
+            return Err(CmsError::Depth((counters.len() / width) as u64));
+        }
+        let hashers = [
+            SipBuildHasher::new_with_keys(seeds[0], seeds[1]),
+            SipBuildHasher::new_with_keys(seeds[2], seeds[3]),
+        ];
+        let counters = VecStorage { counters, width };
+        Ok(Self::with_storage(counters, hashers, width, depth))
+    }
+
+    fn read_header<'b>(bytes: &'b [u8], magic: &[u8; 4]) -> Result<(Self, &'b [u8]), &'static str> {
+        if bytes.len() < SKETCH_HEADER_LEN || bytes[..4] != *magic {
+            return Err("Not a serialized sketch");
+        }
+        if bytes[4] != SKETCH_VERSION {
+            return Err("Unsupported sketch version");
+        }
+        let config = SketchConfig::from_bytes(&bytes[5..5 + CONFIG_LEN])?;
+        let mut cms = Self::from_config(&config).map_err(|_| "Invalid sketch configuration")?;
+        let state = 5 + CONFIG_LEN;
+        cms.total = u64_at(bytes, state);
+        cms.reset_idx = usize::try_from(u64_at(bytes, state + 8))
+            .ok()
+            .filter(|&idx| idx <= cms.mask)
+            .ok_or("Invalid reset index")?;
+        cms.updated_at = Duration::from_nanos(u64_at(bytes, state + 16));
+        Ok((cms, &bytes[SKETCH_HEADER_LEN..]))
+    }
 
-            pub fn estimate<Q: ?Sized>(&self, key: &Q) -> $Counter
-            where
-                Q: Hash,
-                K: Borrow<Q>,
+    /// Restores a sketch exported with `to_bytes()` or `to_sparse_bytes()`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.starts_with(&SPARSE_MAGIC) {
+            return Self::from_sparse_bytes(bytes);
+        }
+        let (mut cms, counters) = Self::read_header(bytes, &SKETCH_MAGIC)?;
+        let counter_size = mem::size_of::<C>();
+        let row_size = (cms.mask + 1) * counter_size;
+        if counters.len() != row_size * cms.k_num {
+            return Err("Serialized counters have an unexpected length");
+        }
+        for (k_i, row) in counters.chunks_exact(row_size).enumerate() {
+            for (counter, le) in cms
+                .counters
+                .row_mut(k_i)
+                .iter_mut()
+                .zip(row.chunks_exact(counter_size))
             {
-                let mut hashes = [0u64, 0u64];
-                (0..self.k_num)
-                    .map(|k_i| {
-                        let offset = self.offset(&mut hashes, key, k_i);
-                        self.counters[k_i][offset]
-                    })
-                    .min()
-                    .unwrap()
+                let mut buf = C::Bytes::default();
+                buf.as_mut().copy_from_slice(le);
+                *counter = C::from_le_bytes(buf);
             }
+        }
+        Ok(cms)
+    }
 
-            pub fn estimate_memory(
-                capacity: usize,
-                probability: f64,
-                tolerance: f64,
-            ) -> Result<usize, &'static str> {
-                let width = Self::optimal_width(capacity, tolerance);
-                let k_num = Self::optimal_k_num(probability);
-                Ok(width * mem::size_of::<$Counter>() * k_num)
+    fn from_sparse_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        let (mut cms, mut runs) = Self::read_header(bytes, &SPARSE_MAGIC)?;
+        let width = cms.mask + 1;
+        let len = width * cms.k_num;
+        let counter_size = mem::size_of::<C>();
+        let mut idx = 0usize;
+        while !runs.is_empty() {
+            let zeros = read_varint(&mut runs)?;
+            let literals = read_varint(&mut runs)?;
+            idx = usize::try_from(zeros)
+                .ok()
+                .and_then(|zeros| idx.checked_add(zeros))
+                .filter(|&end| end <= len)
+                .ok_or("Serialized counters have an unexpected length")?;
+            let size = usize::try_from(literals)
+                .ok()
+                .filter(|&literals| literals <= len - idx)
+                .map(|literals| literals * counter_size)
+                .filter(|&size| size <= runs.len())
+                .ok_or("Serialized counters have an unexpected length")?;
+            let (les, rest) = runs.split_at(size);
+            for le in les.chunks_exact(counter_size) {
+                let mut buf = C::Bytes::default();
+                buf.as_mut().copy_from_slice(le);
+                cms.counters.row_mut(idx / width)[idx % width] = C::from_le_bytes(buf);
+                idx += 1;
             }
+            runs = rest;
+        }
+        Ok(cms)
+    }
 
-            pub fn clear(&mut self) {
-                for k_i in 0..self.k_num {
-                    for counter in &mut self.counters[k_i] {
-                        *counter = 0
+    /// Same as `from_bytes()`, decoding the counters on up to `threads`
+    /// threads.
+    #[cfg(feature = "std")]
+    pub fn from_bytes_parallel(bytes: &[u8], threads: usize) -> Result<Self, &'static str> {
+        let (mut cms, counters) = Self::read_header(bytes, &SKETCH_MAGIC)?;
+        let counter_size = mem::size_of::<C>();
+        let width = cms.mask + 1;
+        if counters.len() != width * cms.k_num * counter_size {
+            return Err("Serialized counters have an unexpected length");
+        }
+        let threads = threads.clamp(1, cms.k_num);
+        let rows_per_thread = cms.k_num.div_ceil(threads);
+        std::thread::scope(|scope| {
+            for (rows, segment) in cms
+                .counters
+                .as_mut_slice()
+                .chunks_mut(rows_per_thread * width)
+                .zip(counters.chunks(rows_per_thread * width * counter_size))
+            {
+                scope.spawn(move || {
+                    let les = segment.chunks_exact(counter_size);
+                    for (counter, le) in rows.iter_mut().zip(les) {
+                        let mut buf = C::Bytes::default();
+                        buf.as_mut().copy_from_slice(le);
+                        *counter = C::from_le_bytes(buf);
                     }
-                }
-                self.reset_idx = 0;
-                self.hashers = [Self::sip_new(), Self::sip_new()];
+                });
             }
+        });
+        Ok(cms)
+    }
 
-            pub fn reset(&mut self) {
-                for k_i in 0..self.k_num {
-                    for counter in &mut self.counters[k_i] {
-                        *counter /= 2;
-                    }
-                }
-                self.reset_idx = 0;
+    /// Restores a sketch exported with `to_quantized_bytes()`.
+    #[cfg(feature = "std")]
+    pub fn from_quantized_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        let (mut cms, payload) = Self::read_header(bytes, &QUANTIZED_MAGIC)?;
+        let width = cms.mask + 1;
+        if payload.len() != 8 + width * cms.k_num {
+            return Err("Serialized counters have an unexpected length");
+        }
+        let max = C::from_u64(u64_at(payload, 0))
+            .ok_or("Counter range is too large for the counter type")?;
+        let quantizer = LogQuantizer::new(max.to_u64());
+        for (k_i, row) in payload[8..].chunks_exact(width).enumerate() {
+            for (counter, &code) in cms.counters.row_mut(k_i).iter_mut().zip(row) {
+                *counter = C::from_u64(quantizer.decode(code)).unwrap_or(max);
             }
+        }
+        Ok(cms)
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, &'static str> {
+        Self::from_bytes(&base64::decode(encoded)?)
+    }
 
-            pub fn reset_next(&mut self) -> Option<usize> {
-                let idx = self.reset_idx;
-                for k_i in 0..self.k_num {
-                    self.counters[k_i][idx] /= 2
+    /// Sums serialized sketches (as produced by `to_bytes()`) read one
+    /// after the other from `readers`.
+    ///
+    /// Counters are streamed into the accumulator row by row, so only one
+    /// sketch is held in memory. All sketches must share the same
+    /// dimensions and seeds.
+    #[cfg(feature = "std")]
+    pub fn merge_from_readers<I, R>(readers: I) -> Result<Self, &'static str>
+    where
+        I: IntoIterator<Item = R>,
+        R: Read,
+    {
+        let mut acc: Option<Self> = None;
+        let mut header = [0u8; SKETCH_HEADER_LEN];
+        let mut row = Vec::new();
+        for mut reader in readers {
+            reader
+                .read_exact(&mut header)
+                .map_err(|_| "Unable to read a serialized sketch")?;
+            let (sketch, _) = Self::read_header(&header, &SKETCH_MAGIC)?;
+            let acc = match &mut acc {
+                None => acc.insert(sketch),
+                Some(acc) => {
+                    acc.is_mergeable(&sketch)
+                        .map_err(|_| "Sketches are not mergeable")?;
+                    acc.total = acc.total.saturating_add(sketch.total);
+                    acc.updated_at = max(acc.updated_at, sketch.updated_at);
+                    acc
                 }
-                let next = idx.wrapping_add(1) & self.mask;
-                self.reset_idx = next;
-                if next != 0 {
-                    Some(next)
-                } else {
-                    None
+            };
+            let counter_size = mem::size_of::<C>();
+            row.resize((acc.mask + 1) * counter_size, 0);
+            for k_i in 0..acc.k_num {
+                reader
+                    .read_exact(&mut row)
+                    .map_err(|_| "Unable to read a serialized sketch")?;
+                let ceiling = acc.ceiling;
+                for (counter, le) in acc
+                    .counters
+                    .row_mut(k_i)
+                    .iter_mut()
+                    .zip(row.chunks_exact(counter_size))
+                {
+                    let mut buf = C::Bytes::default();
+                    buf.as_mut().copy_from_slice(le);
+                    let value = C::from_le_bytes(buf);
+                    *counter = min(counter.saturating_add(value), ceiling);
                 }
             }
+        }
+        acc.ok_or("No sketches to merge")
+    }
+}
 
-            fn optimal_width(capacity: usize, tolerance: f64) -> usize {
-                let e = tolerance / (capacity as f64);
-                let width = (2.0 / e).round() as usize;
-                max(2, width)
-                    .checked_next_power_of_two()
-                    .expect("Width would be way too large")
-            }
+#[cfg(feature = "std")]
+impl<'a, K, C> CountMinSketch<K, C, SliceStorage<'a, C>>
+where
+    K: Hash,
+    C: Counter,
+{
+    pub fn from_buffer(
+        buffer: &'a mut [C],
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+    ) -> Result<Self, CmsError> {
+        let (width, k_num) = Self::dimensions(capacity, probability, tolerance)?;
+        let len = width * k_num;
+        if buffer.len() < len {
+            return Err(CmsError::BufferTooSmall { needed: len });
+        }
+        let counters = &mut buffer[..len];
+        for counter in counters.iter_mut() {
+            *counter = C::ZERO
+        }
+        let hashers = [Self::sip_new().into(), Self::sip_new().into()];
+        let counters = SliceStorage { counters, width };
+        Ok(Self::with_storage(counters, hashers, width, k_num))
+    }
+}
 
-            fn mask(width: usize) -> usize {
-                assert!(width > 1);
-                assert_eq!(width & (width - 1), 0);
-                width - 1
-            }
+impl<K, C, S> CountMinSketch<K, C, S>
+where
+    K: Hash,
+    C: Counter,
+    S: Storage<C>,
+{
+    /// Opens a sketch over existing counters, with the dimensions, hash keys,
+    /// ceiling and update policies of `config`. Unlike `from_buffer()`, the counters are
+    /// kept, so storage that persists them, such as a file-backed mapping,
+    /// can be reopened with the same configuration.
+    ///
+    /// `total()` and `updated_at()` are not part of the counters, and start
+    /// from zero.
+    pub fn from_storage(counters: S, config: &SketchConfig) -> Result<Self, CmsError> {
+        if config.counter_size != mem::size_of::<C>() as u8 {
+            return Err(CmsError::CounterSize(config.counter_size));
+        }
+        let width = usize::try_from(config.width).map_err(|_| CmsError::TooLarge)?;
+        let k_num = usize::try_from(config.depth).map_err(|_| CmsError::TooLarge)?;
+        Self::check_dimensions(width, k_num)?;
+        let ceiling = C::from_u64(config.ceiling).ok_or(CmsError::Ceiling(config.ceiling))?;
+        if counters.depth() < k_num {
+            return Err(CmsError::BufferTooSmall {
+                needed: width * k_num,
+            });
+        }
+        if let Some(row) = (0..k_num)
+            .map(|k_i| counters.row(k_i))
+            .find(|row| row.len() != width)
+        {
+            return Err(CmsError::Width(row.len() as u64));
+        }
+        let hashers = [
+            SipBuildHasher::new_with_keys(config.seeds[0], config.seeds[1]),
+            SipBuildHasher::new_with_keys(config.seeds[2], config.seeds[3]),
+        ];
+        let mut cms = Self::with_storage(counters, hashers, width, k_num);
+        cms.ceiling = ceiling;
+        cms.apply_policies(config);
+        Ok(cms)
+    }
+}
 
-            fn optimal_k_num(probability: f64) -> usize {
-                max(1, ((1.0 - probability).ln() / 0.5f64.ln()) as usize)
-            }
+impl<K, C, H> CountMinSketch<K, C, VecStorage<C>, NoNormalizer, H>
+where
+    K: Hash,
+    C: Counter,
+    H: BuildHasher,
+{
+    /// Same as `new()`, hashing keys with `hashers` instead of SipHash.
+    ///
+    /// The two hashers must be independent, typically the same algorithm with
+    /// different seeds. Sketches are mergeable if their hashers compare equal.
+    /// `config()`, serialization and `clear()` are only available with the
+    /// default `SipBuildHasher`.
+    pub fn with_hashers(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        hashers: [H; 2],
+    ) -> Result<Self, CmsError> {
+        let (width, k_num) = Self::dimensions(capacity, probability, tolerance)?;
+        let counters = VecStorage::new(width, k_num);
+        Ok(Self::with_storage(counters, hashers, width, k_num))
+    }
+}
 
-            fn sip_new() -> FastHasher {
-                let mut rng = rand::thread_rng();
-                FastHasher::new_with_keys(rng.next_u64(), rng.next_u64())
-            }
+impl<K, C, S, H> CountMinSketch<K, C, S, NoNormalizer, H>
+where
+    K: Hash,
+    C: Counter,
+    S: Storage<C>,
+    H: BuildHasher,
+{
+    fn with_storage(counters: S, hashers: [H; 2], width: usize, k_num: usize) -> Self {
+        CountMinSketch {
+            counters,
+            hashers,
+            mask: Self::mask(width),
+            k_num,
+            reset_idx: 0,
+            total: 0,
+            updated_at: Duration::ZERO,
+            ceiling: C::MAX,
+            rounding: Rounding::Down,
+            conservative: true,
+            decay_policy: DecayPolicy::Never,
+            inserts_since_decay: 0,
+            decayed_at: Duration::ZERO,
+            clock: None,
+            overflow_policy: OverflowPolicy::Saturate,
+            rejected_updates: 0,
+            overflow_handler: None,
+            saturated_updates: 0,
+            pinned: Vec::new(),
+            hash_cache: None,
+            #[cfg(feature = "std")]
+            reservoir: None,
+            heavy_hitters: None,
+            normalizer: NoNormalizer,
+            phantom_k: PhantomData,
+        }
+    }
+}
 
-            fn offset<Q: ?Sized>(&self, hashes: &mut [u64; 2], key: &Q, k_i: usize) -> usize
-            where
-                Q: Hash,
-                K: Borrow<Q>,
-            {
-                if k_i < 2 {
-                    let sip = &mut self.hashers[k_i as usize].clone();
-                    key.hash(sip);
-                    let hash = sip.finish();
-                    hashes[k_i as usize] = hash;
-                    hash as usize & self.mask
-                } else {
-                    hashes[0]
-                        .wrapping_add((k_i as u64).wrapping_mul(hashes[1]) % 0xffffffffffffffc5)
-                        as usize
-                        & self.mask
-                }
-            }
+impl<K, C, S, N, H> CountMinSketch<K, C, S, N, H>
+where
+    K: Hash,
+    C: Counter,
+    S: Storage<C>,
+    H: BuildHasher,
+{
+    /// Hashes keys through `normalizer`. This must be set before the
+    /// sketch is used, and drops the hash cache, pinned keys and recorded
+    /// heavy hitters.
+    pub fn with_normalizer<N2>(self, normalizer: N2) -> CountMinSketch<K, C, S, N2, H> {
+        CountMinSketch {
+            counters: self.counters,
+            hashers: self.hashers,
+            mask: self.mask,
+            k_num: self.k_num,
+            reset_idx: self.reset_idx,
+            total: self.total,
+            updated_at: self.updated_at,
+            ceiling: self.ceiling,
+            rounding: self.rounding,
+            conservative: self.conservative,
+            decay_policy: self.decay_policy,
+            inserts_since_decay: self.inserts_since_decay,
+            decayed_at: self.decayed_at,
+            clock: self.clock,
+            overflow_policy: self.overflow_policy,
+            rejected_updates: self.rejected_updates,
+            overflow_handler: self.overflow_handler,
+            saturated_updates: self.saturated_updates,
+            pinned: Vec::new(),
+            hash_cache: self.hash_cache.map(|c| HashCache::new(c.capacity())),
+            #[cfg(feature = "std")]
+            reservoir: self.reservoir,
+            heavy_hitters: self.heavy_hitters.map(|h| HeavyHitters::new(h.threshold())),
+            normalizer,
+            phantom_k: PhantomData,
         }
-    };
-} // macro_rules! cms_define
+    }
 
-cms_define!(CountMinSketch8, u8);
-cms_define!(CountMinSketch16, u16);
-cms_define!(CountMinSketch32, u32);
-cms_define!(CountMinSketch64, u64);
+    /// Number of counters per row.
+    pub fn width(&self) -> usize {
+        self.mask + 1
+    }
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn test_overflow() {
-        use crate::CountMinSketch8;
+    /// Number of rows.
+    pub fn depth(&self) -> usize {
+        self.k_num
+    }
 
-        let mut cms = CountMinSketch8::<&str>::new(100, 0.95, 10.0).unwrap();
-        for _ in 0..300 {
-            cms.increment("key");
+    /// Counters of every row, in order, as stored by the sketch.
+    pub fn rows(&self) -> impl Iterator<Item = &[C]> + '_ {
+        (0..self.k_num).map(move |k_i| self.counters.row(k_i))
+    }
+
+    /// Relative error ε of this sketch: an estimate exceeds the true count
+    /// by more than `epsilon() * total()` with probability at most
+    /// `failure_probability()`.
+    pub fn epsilon(&self) -> f64 {
+        2.0 / self.width() as f64
+    }
+
+    /// Probability δ that an estimate exceeds the bound given by
+    /// `epsilon()`, halved by every row.
+    pub fn failure_probability(&self) -> f64 {
+        (0..self.k_num).fold(1.0, |delta, _| delta * 0.5)
+    }
+
+    /// Checks that `other` has the same dimensions and hash keys, so that
+    /// their counters can be combined cell by cell.
+    pub fn is_mergeable<S2, N2>(
+        &self,
+        other: &CountMinSketch<K, C, S2, N2, H>,
+    ) -> Result<(), Incompatibility>
+    where
+        S2: Storage<C>,
+        H: PartialEq,
+    {
+        if self.mask != other.mask {
+            return Err(Incompatibility::Width {
+                ours: self.mask + 1,
+                theirs: other.mask + 1,
+            });
         }
-        assert_eq!(cms.estimate("key"), u8::max_value());
+        if self.k_num != other.k_num {
+            return Err(Incompatibility::Depth {
+                ours: self.k_num,
+                theirs: other.k_num,
+            });
+        }
+        if self.hashers != other.hashers {
+            return Err(Incompatibility::Seeds);
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_increment() {
-        use crate::CountMinSketch16;
+    /// Adds the counters of `other`, which must have the same dimensions
+    /// and seeds, to this sketch. Counters saturate at the ceiling.
+    ///
+    /// With the standard update, the result is the sketch of both streams.
+    /// With conservative update, it may overestimate more.
+    pub fn merge<S2, N2>(
+        &mut self,
+        other: &CountMinSketch<K, C, S2, N2, H>,
+    ) -> Result<(), Incompatibility>
+    where
+        S2: Storage<C>,
+        H: PartialEq,
+    {
+        self.is_mergeable(other)?;
+        for k_i in 0..self.k_num {
+            C::add_slice(
+                self.counters.row_mut(k_i),
+                other.counters.row(k_i),
+                self.ceiling,
+            );
+        }
+        self.total = self.total.saturating_add(other.total);
+        self.updated_at = max(self.updated_at, other.updated_at);
+        Ok(())
+    }
 
-        let mut cms = CountMinSketch16::<&str>::new(100, 0.95, 10.0).unwrap();
-        for _ in 0..300 {
-            cms.increment("key");
+    /// Same as `merge()`, with the counters of `other` multiplied by
+    /// `weight` first and rounded with the rounding mode, to fold in older
+    /// sketches with less weight than recent ones.
+    ///
+    /// `weight` is clamped to `[0, 1]`, NaN counting as 0.
+    pub fn merge_scaled<S2, N2>(
+        &mut self,
+        other: &CountMinSketch<K, C, S2, N2, H>,
+        weight: f64,
+    ) -> Result<(), Incompatibility>
+    where
+        S2: Storage<C>,
+        H: PartialEq,
+    {
+        self.is_mergeable(other)?;
+        let weight = if weight > 0.0 { weight.min(1.0) } else { 0.0 };
+        let (rounding, ceiling) = (self.rounding, self.ceiling);
+        for k_i in 0..self.k_num {
+            let row = self.counters.row_mut(k_i);
+            for (counter, &theirs) in row.iter_mut().zip(other.counters.row(k_i)) {
+                let scaled = C::from_u64(rounding.scale(theirs.to_u64(), weight)).unwrap_or(theirs);
+                *counter = min(counter.saturating_add(scaled), ceiling);
+            }
         }
-        assert_eq!(cms.estimate("key"), 300);
+        let total = rounding.scale(other.total, weight);
+        self.total = self.total.saturating_add(total);
+        self.updated_at = max(self.updated_at, other.updated_at);
+        Ok(())
     }
 
-    #[test]
-    fn test_increment_multi() {
-        use crate::CountMinSketch64;
+    /// Keeps the highest of each pair of counters, for replicas that may
+    /// have counted the same events: the result never undercounts a key
+    /// counted by either sketch, without adding up events seen by both.
+    /// `other` must have the same dimensions and seeds.
+    ///
+    /// `total()` becomes the highest of both totals.
+    pub fn union_max<S2, N2>(
+        &mut self,
+        other: &CountMinSketch<K, C, S2, N2, H>,
+    ) -> Result<(), Incompatibility>
+    where
+        S2: Storage<C>,
+        H: PartialEq,
+    {
+        self.is_mergeable(other)?;
+        let ceiling = self.ceiling;
+        for k_i in 0..self.k_num {
+            let row = self.counters.row_mut(k_i);
+            for (counter, &theirs) in row.iter_mut().zip(other.counters.row(k_i)) {
+                *counter = max(*counter, min(theirs, ceiling));
+            }
+        }
+        self.total = max(self.total, other.total);
+        self.updated_at = max(self.updated_at, other.updated_at);
+        Ok(())
+    }
 
-        let mut cms = CountMinSketch64::<u64>::new(100, 0.99, 2.0).unwrap();
-        for i in 0..1_000_000 {
-            cms.increment(&(i % 100));
+    /// Estimates the inner product of the two streams, the sum over keys of
+    /// their counts in both, such as the size of an equi-join: the lowest
+    /// dot product of corresponding rows. `other` must have the same
+    /// dimensions and seeds.
+    ///
+    /// With the standard update, the estimate never undercounts, and
+    /// exceeds the true value by at most `epsilon() * total() *
+    /// other.total()` with probability `1 - failure_probability()`.
+    pub fn inner_product<S2, N2>(
+        &self,
+        other: &CountMinSketch<K, C, S2, N2, H>,
+    ) -> Result<u128, Incompatibility>
+    where
+        S2: Storage<C>,
+        H: PartialEq,
+    {
+        self.is_mergeable(other)?;
+        let product = (0..self.k_num)
+            .map(|k_i| {
+                self.counters
+                    .row(k_i)
+                    .iter()
+                    .zip(other.counters.row(k_i))
+                    .fold(0u128, |sum, (a, b)| {
+                        sum.saturating_add(a.to_u64() as u128 * b.to_u64() as u128)
+                    })
+            })
+            .fold(u128::MAX, min);
+        Ok(product)
+    }
+
+    /// Cosine similarity of the two streams, from 0.0 (no key in common)
+    /// to 1.0 (same distribution up to a factor), computed from
+    /// `inner_product()`. `other` must have the same dimensions and seeds.
+    ///
+    /// Collisions make unrelated streams look somewhat similar. Returns 0.0
+    /// if either sketch is empty.
+    #[cfg(feature = "std")]
+    pub fn similarity<S2, N2>(
+        &self,
+        other: &CountMinSketch<K, C, S2, N2, H>,
+    ) -> Result<f64, Incompatibility>
+    where
+        S2: Storage<C>,
+        H: PartialEq,
+    {
+        let product = self.inner_product(other)? as f64;
+        let norms = self.inner_product(self)? as f64 * other.inner_product(other)? as f64;
+        if norms == 0.0 {
+            return Ok(0.0);
         }
-        for key in 0..100 {
-            assert!(cms.estimate(&key) >= 9_000);
+        Ok((product / norms.sqrt()).min(1.0))
+    }
+
+    pub fn add<Q>(&mut self, key: &Q, value: C)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        self.add_hashes(&hashes, value);
+    }
+
+    // Returns the new estimate
+    fn add_hashes(&mut self, hashes: &[u64; 2], value: C) -> C {
+        if let (DecayPolicy::EveryDuration(_), Some(clock)) = (self.decay_policy, &self.clock) {
+            let now = clock.now();
+            self.age_to(now);
         }
-        cms.reset();
-        for key in 0..100 {
-            assert!(cms.estimate(&key) < 11_000);
+        let estimate = match self.update_counters(hashes, value) {
+            Ok(estimate) => estimate,
+            Err(err) => {
+                self.rejected_updates += 1;
+                if let Some(handler) = self.overflow_handler {
+                    handler(err);
+                }
+                return self.estimate_hashes(hashes);
+            }
+        };
+        if let DecayPolicy::EveryNInserts(n) = self.decay_policy {
+            self.inserts_since_decay += 1;
+            if self.inserts_since_decay >= n {
+                self.reset();
+                return self.estimate_hashes(hashes);
+            }
         }
+        estimate
     }
+
+    fn update_counters(&mut self, hashes: &[u64; 2], value: C) -> Result<C, SaturationError> {
+        match self.overflow_policy {
+            OverflowPolicy::Saturate => {}
+            OverflowPolicy::Error => {
+                if self.would_overflow(hashes, value) {
+                    return Err(SaturationError {
+                        ceiling: self.ceiling.to_u64(),
+                    });
+                }
+            }
+            OverflowPolicy::Wrap => return Ok(self.wrap_counters(hashes, value)),
+        }
+        self.total = self.total.saturating_add(value.to_u64());
+        if !self.conservative {
+            let ceiling = self.ceiling;
+            let (mut lowest, mut saturated) = (C::MAX, false);
+            for k_i in 0..self.k_num {
+                let offset = self.row_offset(hashes, k_i);
+                let counter = &mut self.counters.row_mut(k_i)[offset];
+                saturated |= value > ceiling.saturating_sub(*counter);
+                *counter = min(counter.saturating_add(value), ceiling);
+                lowest = min(lowest, *counter);
+            }
+            self.saturated_updates += saturated as u64;
+            return Ok(lowest);
+        }
+        let lowest = (0..self.k_num)
+            .map(|k_i| {
+                let offset = self.row_offset(hashes, k_i);
+                self.counters.row(k_i)[offset]
+            })
+            .fold(C::MAX, min);
+        self.saturated_updates += (value > self.ceiling.saturating_sub(lowest)) as u64;
+        // The lowest counters are raised to `target`, and none stay below
+        let target = min(lowest.saturating_add(value), self.ceiling);
+        for k_i in 0..self.k_num {
+            let offset = self.row_offset(hashes, k_i);
+            let counter = &mut self.counters.row_mut(k_i)[offset];
+            if *counter < target {
+                *counter = target;
+            }
+        }
+        Ok(target)
+    }
+
+    fn wrap_counters(&mut self, hashes: &[u64; 2], value: C) -> C {
+        self.total = self.total.saturating_add(value.to_u64());
+        let mut lowest = C::MAX;
+        for k_i in 0..self.k_num {
+            let offset = self.row_offset(hashes, k_i);
+            let counter = &mut self.counters.row_mut(k_i)[offset];
+            *counter = counter.wrapping_add(value);
+            lowest = min(lowest, *counter);
+        }
+        lowest
+    }
+
+    // Whether adding `value` would take a counter past the ceiling: the
+    // lowest one with conservative update, any of them otherwise
+    fn would_overflow(&self, hashes: &[u64; 2], value: C) -> bool {
+        let counters = (0..self.k_num).map(|k_i| {
+            let offset = self.row_offset(hashes, k_i);
+            self.counters.row(k_i)[offset]
+        });
+        let counter = if self.conservative {
+            counters.fold(C::MAX, min)
+        } else {
+            counters.fold(C::ZERO, max)
+        };
+        value > self.ceiling.saturating_sub(counter)
+    }
+
+    /// Same as `add()`, returning the new estimate of `key` from the same
+    /// pass over the rows.
+    pub fn add_and_estimate<Q>(&mut self, key: &Q, value: C) -> C
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        self.add_hashes(&hashes, value)
+    }
+
+    /// Same as `add_and_estimate()`, but refuses updates that would take a
+    /// counter past the ceiling, whatever the overflow policy, leaving the
+    /// sketch unchanged, so that no count added through `try_add()` is
+    /// truncated by saturation.
+    pub fn try_add<Q>(&mut self, key: &Q, value: C) -> Result<C, SaturationError>
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        if self.would_overflow(&hashes, value) {
+            return Err(SaturationError {
+                ceiling: self.ceiling.to_u64(),
+            });
+        }
+        Ok(self.add_hashes(&hashes, value))
+    }
+
+    pub fn increment_and_estimate<Q>(&mut self, key: &Q) -> C
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        self.add_and_estimate(key, C::ONE)
+    }
+
+    pub fn increment<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        self.add(key, C::ONE)
+    }
+
+    /// Same as calling `add()` for every item, hashing keys in batches and
+    /// prefetching their cells before updating them, to overlap cache misses.
+    pub fn add_all<I>(&mut self, items: I)
+    where
+        I: IntoIterator<Item = (K, C)>,
+        N: NormalizeKey<K>,
+    {
+        let mut items = items.into_iter();
+        let mut batch = Vec::with_capacity(BATCH_LEN);
+        loop {
+            batch.clear();
+            for (key, value) in items.by_ref().take(BATCH_LEN) {
+                let hashes = self.normalized_key_hashes(&key);
+                self.prefetch(&hashes);
+                batch.push((hashes, value));
+            }
+            if batch.is_empty() {
+                return;
+            }
+            for (hashes, value) in &batch {
+                self.add_hashes(hashes, *value);
+            }
+        }
+    }
+
+    pub fn increment_all<I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = K>,
+        N: NormalizeKey<K>,
+    {
+        self.add_all(keys.into_iter().map(|key| (key, C::ONE)))
+    }
+
+    /// Subtracts `value` from the counters of `key`, for turnstile streams
+    /// where items leave as well as arrive.
+    ///
+    /// Counters floor at zero, so removing more than was added makes the
+    /// estimates of colliding keys too low. Counters that hit the ceiling
+    /// no longer hold the sum of the updates, and will also end up too low.
+    /// Conservative update skips rows, so it must be disabled with
+    /// `set_conservative_update(false)`.
+    pub fn remove<Q>(&mut self, key: &Q, value: C) -> Result<(), CmsError>
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        if self.conservative {
+            return Err(CmsError::ConservativeUpdate);
+        }
+        let hashes = self.normalized_key_hashes(key);
+        self.total = self.total.saturating_sub(value.to_u64());
+        for k_i in 0..self.k_num {
+            let offset = self.row_offset(&hashes, k_i);
+            let counter = &mut self.counters.row_mut(k_i)[offset];
+            *counter = counter.saturating_sub(value);
+        }
+        Ok(())
+    }
+
+    pub fn decrement<Q>(&mut self, key: &Q) -> Result<(), CmsError>
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        self.remove(key, C::ONE)
+    }
+
+    pub fn estimate<Q>(&self, key: &Q) -> C
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        self.estimate_hashes(&hashes)
+    }
+
+    /// Hashes `key` once, for `add_hashed()` and `estimate_hashed()`.
+    ///
+    /// The result is only meaningful for sketches with the same hashers
+    /// and normalizer, such as sketches built from the same `SketchConfig`,
+    /// whatever their dimensions.
+    pub fn hash_key<Q>(&self, key: &Q) -> HashedKey
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let mut sips = [
+            self.hashers[0].build_hasher(),
+            self.hashers[1].build_hasher(),
+        ];
+        self.normalizer.hash_into(key, &mut sips);
+        HashedKey {
+            hashes: [sips[0].finish(), sips[1].finish()],
+        }
+    }
+
+    pub fn add_hashed(&mut self, key: &HashedKey, value: C) {
+        self.add_hashes(&key.hashes, value);
+    }
+
+    pub fn increment_hashed(&mut self, key: &HashedKey) {
+        self.add_hashed(key, C::ONE)
+    }
+
+    pub fn estimate_hashed(&self, key: &HashedKey) -> C {
+        self.estimate_hashes(&key.hashes)
+    }
+
+    /// Estimates of `keys`, in order.
+    ///
+    /// Keys are hashed and their cells prefetched in batches before being
+    /// read, which is faster than calling `estimate()` in a loop on sketches
+    /// larger than the CPU caches.
+    pub fn estimate_many<Q>(&self, keys: &[Q]) -> Vec<C>
+    where
+        Q: Hash,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let mut estimates = Vec::with_capacity(keys.len());
+        let mut batch = [[0u64; 2]; BATCH_LEN];
+        for chunk in keys.chunks(BATCH_LEN) {
+            for (hashes, key) in batch.iter_mut().zip(chunk) {
+                *hashes = self.normalized_key_hashes(key);
+                self.prefetch(hashes);
+            }
+            estimates.extend(batch[..chunk.len()].iter().map(|h| self.estimate_hashes(h)));
+        }
+        estimates
+    }
+
+    /// Estimate of `key`, with the most it may exceed the true count by:
+    /// `epsilon() * total()`, except with probability
+    /// `failure_probability()`.
+    ///
+    /// The bound doesn't apply to sketches that were decayed with
+    /// `reset()`, nor to counters stuck at the ceiling.
+    pub fn estimate_with_bound<Q>(&self, key: &Q) -> (C, u64)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let bound = (self.total as u128 * 2).div_ceil(self.width() as u128);
+        (self.estimate(key), bound as u64)
+    }
+
+    /// Counter values of `key` across rows, to gauge collision noise.
+    ///
+    /// With an even depth, `median` is the lower of the two middle values.
+    pub fn estimate_spread<Q>(&self, key: &Q) -> Spread<C>
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        let mut values: Vec<C> = (0..self.k_num)
+            .map(|k_i| self.counters.row(k_i)[self.row_offset(&hashes, k_i)])
+            .collect();
+        values.sort_unstable();
+        let (min, max) = (values[0], values[self.k_num - 1]);
+        let dispersion = if max == C::ZERO {
+            0.0
+        } else {
+            (max.to_u64() - min.to_u64()) as f64 / max.to_u64() as f64
+        };
+        Spread {
+            min,
+            median: values[(self.k_num - 1) / 2],
+            max,
+            dispersion,
+        }
+    }
+
+    /// Estimates the Zipf exponent of the stream: 0.0 for uniform traffic,
+    /// around 1.0 for typical web-like traffic, and more for heavier skew.
+    ///
+    /// The heaviest cells of each row, minus the typical collision noise
+    /// of the row, are fitted against their rank on a log-log scale.
+    /// Mild skew (below 1.0) is overestimated when the sketch is narrow for
+    /// the number of distinct keys. Returns `None` if too few cells stand
+    /// out from the noise.
+    #[cfg(feature = "std")]
+    pub fn estimate_skew(&self) -> Option<f64> {
+        let width = self.mask + 1;
+        let ranks = max(2, (width as f64).sqrt() as usize);
+        let mut slopes = 0.0;
+        let mut fitted_rows = 0;
+        for k_i in 0..self.k_num {
+            let mut values: Vec<u64> = self.counters.row(k_i).iter().map(|c| c.to_u64()).collect();
+            values.sort_unstable_by(|a, b| b.cmp(a));
+            let noise = values[width / 2];
+            let points: Vec<(f64, f64)> = values
+                .iter()
+                .take(ranks)
+                .take_while(|&&v| v > noise)
+                .enumerate()
+                .map(|(rank, &v)| (((rank + 1) as f64).ln(), ((v - noise) as f64).ln()))
+                .collect();
+            if points.len() < 2 {
+                continue;
+            }
+            let n = points.len() as f64;
+            let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+            let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+            let (cov, var) = points.iter().fold((0.0, 0.0), |(cov, var), p| {
+                let dx = p.0 - mean_x;
+                (cov + dx * (p.1 - mean_y), var + dx * dx)
+            });
+            slopes += cov / var;
+            fitted_rows += 1;
+        }
+        if fitted_rows == 0 {
+            return None;
+        }
+        Some((-slopes / fitted_rows as f64).max(0.0))
+    }
+
+    /// Compares the estimate of `key` with the one in `earlier`, a previous
+    /// snapshot of the same sketch.
+    pub fn delta<Q, S2, N2>(
+        &self,
+        earlier: &CountMinSketch<K, C, S2, N2, H>,
+        key: &Q,
+    ) -> Result<Delta<C>, Incompatibility>
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+        S2: Storage<C>,
+        H: PartialEq,
+    {
+        self.is_mergeable(earlier)?;
+        let hashes = self.normalized_key_hashes(key);
+        let (current, previous) = (
+            self.estimate_hashes(&hashes),
+            earlier.estimate_hashes(&hashes),
+        );
+        Ok(Delta {
+            current,
+            previous,
+            change: current.to_u64() as i128 - previous.to_u64() as i128,
+        })
+    }
+
+    /// Same as `delta()` for each of the candidate `keys`, in order.
+    pub fn deltas<'q, Q, S2, N2, I>(
+        &self,
+        earlier: &CountMinSketch<K, C, S2, N2, H>,
+        keys: I,
+    ) -> Result<Vec<(&'q Q, Delta<C>)>, Incompatibility>
+    where
+        Q: Hash + ?Sized + 'q,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+        S2: Storage<C>,
+        H: PartialEq,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        self.is_mergeable(earlier)?;
+        keys.into_iter()
+            .map(|key| Ok((key, self.delta(earlier, key)?)))
+            .collect()
+    }
+
+    /// Returns `true` if the estimate for `key` is at least `threshold`.
+    ///
+    /// Stops at the first row below the threshold, which is cheaper than
+    /// `estimate()` for keys that are mostly absent.
+    pub fn is_frequent<Q>(&self, key: &Q, threshold: C) -> bool
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        self.reaches_hashes(&hashes, threshold)
+    }
+
+    /// Compares the estimates of `a` and `b`, as needed by TinyLFU-style
+    /// admission to choose between a candidate and an eviction victim.
+    pub fn compare<Q>(&self, a: &Q, b: &Q) -> Ordering
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let (a, b) = (self.normalized_key_hashes(a), self.normalized_key_hashes(b));
+        self.estimate_hashes(&a).cmp(&self.estimate_hashes(&b))
+    }
+
+    /// Returns `true` if the estimate of `a` is at least the one of `b`.
+    ///
+    /// Stops at the first row of `a` below the estimate of `b`.
+    pub fn frequency_ge<Q>(&self, a: &Q, b: &Q) -> bool
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let (a, b) = (self.normalized_key_hashes(a), self.normalized_key_hashes(b));
+        self.reaches_hashes(&a, self.estimate_hashes(&b))
+    }
+
+    fn reaches_hashes(&self, hashes: &[u64; 2], threshold: C) -> bool {
+        (0..self.k_num).all(|k_i| {
+            let offset = self.row_offset(hashes, k_i);
+            self.counters.row(k_i)[offset] >= threshold
+        })
+    }
+
+    fn estimate_hashes(&self, hashes: &[u64; 2]) -> C {
+        let mut lowest = C::MAX;
+        for k_i in 0..self.k_num {
+            let offset = self.row_offset(hashes, k_i);
+            lowest = min(lowest, self.counters.row(k_i)[offset]);
+            // The minimum can't get any lower
+            if lowest == C::ZERO {
+                break;
+            }
+        }
+        lowest
+    }
+
+    /// Adds `value` to `key` and records `clock.now()` as the time the
+    /// counters were last updated.
+    ///
+    /// With `DecayPolicy::EveryDuration`, the sketch is first halved once
+    /// per period elapsed since it was last aged.
+    pub fn add_at<Q, T>(&mut self, key: &Q, value: C, clock: T)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+        T: Clock,
+    {
+        let now = clock.now();
+        self.age_to(now);
+        self.add(key, value);
+        self.updated_at = max(self.updated_at, now);
+    }
+
+    fn age_to(&mut self, now: Duration) {
+        let period = match self.decay_policy {
+            DecayPolicy::EveryDuration(period) if !period.is_zero() => period.as_nanos(),
+            _ => return,
+        };
+        let periods = now.saturating_sub(self.decayed_at).as_nanos() / period;
+        if periods == 0 {
+            return;
+        }
+        // Counters are zero after 64 halvings, no need to go further
+        for _ in 0..periods.min(u64::BITS as u128) {
+            self.reset();
+        }
+        self.decayed_at =
+            Duration::from_nanos((self.decayed_at.as_nanos() + periods * period) as u64);
+    }
+
+    pub fn updated_at(&self) -> Duration {
+        self.updated_at
+    }
+
+    pub fn set_updated_at<T>(&mut self, clock: T)
+    where
+        T: Clock,
+    {
+        self.updated_at = clock.now();
+    }
+
+    /// Estimate discounted by `0.5^(age / half_life)`, where `age` is the
+    /// time elapsed between the last update and `clock.now()`. The sketch
+    /// isn't modified.
+    #[cfg(feature = "std")]
+    pub fn estimate_decayed<Q, T>(&self, key: &Q, half_life: Duration, clock: T) -> f64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+        T: Clock,
+    {
+        let now = clock.now();
+        let estimate = self.estimate(key).to_u64() as f64;
+        let age = now.saturating_sub(self.updated_at);
+        if age.is_zero() {
+            return estimate;
+        }
+        if half_life.is_zero() {
+            return 0.0;
+        }
+        estimate * 0.5f64.powf(age.as_secs_f64() / half_life.as_secs_f64())
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Laplace-smoothed probability of `key`: `(estimate + 1) / (total + vocabulary_size)`.
+    pub fn probability<Q>(&self, key: &Q, vocabulary_size: u64) -> f64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let denominator = self.total as f64 + vocabulary_size as f64;
+        if denominator == 0.0 {
+            return 0.0;
+        }
+        (self.estimate(key).to_u64() as f64 + 1.0) / denominator
+    }
+
+    /// Estimates `P(b | a)` from this sketch of `a` occurrences and a
+    /// sketch of `(a, b)` pair occurrences.
+    pub fn conditional_probability<B, S2>(
+        &self,
+        pairs: &CountMinSketch<(K, B), C, S2, NoNormalizer, H>,
+        a: &K,
+        b: &B,
+    ) -> Result<f64, CmsError>
+    where
+        B: Hash,
+        S2: Storage<C>,
+        N: NormalizeKey<K>,
+    {
+        let count_a = self.estimate_hashes(&self.normalized_key_hashes(a));
+        if count_a == C::ZERO {
+            return Err(CmsError::Unobserved);
+        }
+        let count_ab = pairs.estimate_hashes(&pairs.key_hashes(&(a, b)));
+        Ok((count_ab.to_u64() as f64 / count_a.to_u64() as f64).min(1.0))
+    }
+
+    /// Pointwise mutual information `log2(P(a, b) / (P(a) P(b)))`, estimated
+    /// from this item sketch and a sketch of co-occurring `(a, b)` pairs.
+    ///
+    /// Counts below `min_count` are too noisy to be meaningful and are rejected.
+    #[cfg(feature = "std")]
+    pub fn pmi<S2>(
+        &self,
+        pairs: &CountMinSketch<(K, K), C, S2, NoNormalizer, H>,
+        a: &K,
+        b: &K,
+        min_count: C,
+    ) -> Result<f64, CmsError>
+    where
+        S2: Storage<C>,
+        N: NormalizeKey<K>,
+    {
+        let min_count = max(min_count, C::ONE);
+        let count_a = self.estimate_hashes(&self.normalized_key_hashes(a));
+        let count_b = self.estimate_hashes(&self.normalized_key_hashes(b));
+        let count_ab = pairs.estimate_hashes(&pairs.key_hashes(&(a, b)));
+        if count_a < min_count || count_b < min_count || count_ab < min_count {
+            return Err(CmsError::CountTooLow);
+        }
+        let p_a = count_a.to_u64() as f64 / self.total as f64;
+        let p_b = count_b.to_u64() as f64 / self.total as f64;
+        let p_ab = count_ab.to_u64() as f64 / pairs.total as f64;
+        Ok((p_ab / (p_a * p_b)).log2())
+    }
+
+    /// Caches the hashes of up to `capacity` keys (rounded up to a power
+    /// of two, at most 65536) for the `*_cached()` methods.
+    ///
+    /// The cache is direct-mapped: a key evicts the one using the same
+    /// slot, so lookups cost a single cheap hash and comparison.
+    pub fn enable_hash_cache(&mut self, capacity: usize) {
+        self.hash_cache = Some(HashCache::new(capacity));
+    }
+
+    pub fn disable_hash_cache(&mut self) {
+        self.hash_cache = None;
+    }
+
+    pub fn add_cached(&mut self, key: &K, value: C)
+    where
+        K: Eq + Clone,
+        N: NormalizeKey<K>,
+    {
+        let hashes = self.cached_key_hashes(key);
+        self.add_hashes(&hashes, value);
+    }
+
+    pub fn increment_cached(&mut self, key: &K)
+    where
+        K: Eq + Clone,
+        N: NormalizeKey<K>,
+    {
+        self.add_cached(key, C::ONE)
+    }
+
+    pub fn estimate_cached(&mut self, key: &K) -> C
+    where
+        K: Eq + Clone,
+        N: NormalizeKey<K>,
+    {
+        let hashes = self.cached_key_hashes(key);
+        self.estimate_hashes(&hashes)
+    }
+
+    /// Keeps a uniform random sample of up to `capacity` of the keys
+    /// added with `add_sampled()`.
+    #[cfg(feature = "std")]
+    pub fn enable_reservoir(&mut self, capacity: usize) {
+        self.reservoir = Some(Reservoir::new(capacity));
+    }
+
+    #[cfg(feature = "std")]
+    pub fn disable_reservoir(&mut self) {
+        self.reservoir = None;
+    }
+
+    /// Same as `add()`, also offering `key` to the reservoir, if enabled.
+    #[cfg(feature = "std")]
+    pub fn add_sampled(&mut self, key: &K, value: C)
+    where
+        K: Clone,
+        N: NormalizeKey<K>,
+    {
+        if let Some(reservoir) = &mut self.reservoir {
+            reservoir.offer(key);
+        }
+        self.add(key, value)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn increment_sampled(&mut self, key: &K)
+    where
+        K: Clone,
+        N: NormalizeKey<K>,
+    {
+        self.add_sampled(key, C::ONE)
+    }
+
+    /// Sampled keys with their current estimates. Empty if the reservoir
+    /// is disabled.
+    #[cfg(feature = "std")]
+    pub fn sample(&self) -> Vec<(&K, C)>
+    where
+        N: NormalizeKey<K>,
+    {
+        self.reservoir
+            .iter()
+            .flat_map(|reservoir| reservoir.keys())
+            .map(|key| (key, self.estimate(key)))
+            .collect()
+    }
+
+    /// Records the keys added with `add_tracked()` whose estimate reaches
+    /// `threshold`, replacing previously recorded ones.
+    pub fn enable_heavy_hitters(&mut self, threshold: C) {
+        self.heavy_hitters = Some(HeavyHitters::new(threshold));
+    }
+
+    pub fn disable_heavy_hitters(&mut self) {
+        self.heavy_hitters = None;
+    }
+
+    /// Same as `add()`, also recording `key` as a heavy hitter if its
+    /// estimate reaches the threshold, when enabled.
+    pub fn add_tracked(&mut self, key: &K, value: C)
+    where
+        K: Clone,
+        N: NormalizeKey<K>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        self.add_hashes(&hashes, value);
+        if self.heavy_hitters.is_some() {
+            let estimate = self.estimate_hashes(&hashes);
+            if let Some(heavy_hitters) = &mut self.heavy_hitters {
+                heavy_hitters.offer(key, hashes, estimate);
+            }
+        }
+    }
+
+    pub fn increment_tracked(&mut self, key: &K)
+    where
+        K: Clone,
+        N: NormalizeKey<K>,
+    {
+        self.add_tracked(key, C::ONE)
+    }
+
+    /// Recorded heavy hitters, in the order they crossed the threshold,
+    /// with their current estimates. Keys stay recorded after `reset()`
+    /// lowers them; `clear_keep_seeds()` forgets them.
+    pub fn heavy_hitters(&self) -> impl Iterator<Item = (&K, C)> + '_
+    where
+        N: NormalizeKey<K>,
+    {
+        self.heavy_hitters
+            .iter()
+            .flat_map(|heavy_hitters| heavy_hitters.keys())
+            .map(move |key| (key, self.estimate(key)))
+    }
+
+    pub fn ceiling(&self) -> C {
+        self.ceiling
+    }
+
+    /// Clamps counters at `ceiling` instead of the type maximum,
+    /// including the ones already above it.
+    pub fn set_ceiling(&mut self, ceiling: C) {
+        self.ceiling = ceiling;
+        for k_i in 0..self.k_num {
+            for counter in self.counters.row_mut(k_i) {
+                *counter = min(*counter, ceiling);
+            }
+        }
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    /// Sets how `reset()` and `reset_next()` round halved counters.
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
+    pub fn decay_policy(&self) -> DecayPolicy {
+        self.decay_policy
+    }
+
+    /// Sets when the sketch ages itself with `reset()`, restarting the
+    /// current period from `clock.now()`.
+    ///
+    /// The clock is kept to time the periods of `DecayPolicy::EveryDuration`
+    /// on every update; share a `ManualClock` through an `Arc` to drive it.
+    pub fn set_decay_policy<T>(&mut self, decay_policy: DecayPolicy, clock: T)
+    where
+        T: Clock + Send + Sync + 'static,
+    {
+        self.decay_policy = decay_policy;
+        self.inserts_since_decay = 0;
+        self.decayed_at = clock.now();
+        self.clock = Some(SharedClock::new(clock));
+    }
+
+    pub fn conservative_update(&self) -> bool {
+        self.conservative
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    /// Calls `handler` with every update rejected by `OverflowPolicy::Error`,
+    /// as `add()` and the other update methods can't return the error.
+    pub fn set_overflow_handler(&mut self, handler: Option<fn(SaturationError)>) {
+        self.overflow_handler = handler;
+    }
+
+    /// Number of updates rejected by `OverflowPolicy::Error` since the
+    /// sketch was created or cleared.
+    pub fn rejected_updates(&self) -> u64 {
+        self.rejected_updates
+    }
+
+    /// Number of updates that were truncated by saturation since the sketch
+    /// was created or cleared. A growing count means the counters are too
+    /// narrow, or the ceiling too low, for the traffic.
+    pub fn saturated_updates(&self) -> u64 {
+        self.saturated_updates
+    }
+
+    /// Fraction of the counters that are at the ceiling, which is the
+    /// maximum of the counter type by default.
+    pub fn saturated_ratio(&self) -> f64 {
+        let saturated: usize = (0..self.k_num)
+            .map(|k_i| {
+                self.counters
+                    .row(k_i)
+                    .iter()
+                    .filter(|&&counter| counter == self.ceiling)
+                    .count()
+            })
+            .sum();
+        saturated as f64 / ((self.mask + 1) * self.k_num) as f64
+    }
+
+    /// Dimensions, load and error bound of the sketch, in a single pass
+    /// over the counters.
+    pub fn stats(&self) -> Stats<C> {
+        let (mut non_zero, mut max_counter, mut sum) = (0usize, C::ZERO, 0u128);
+        for k_i in 0..self.k_num {
+            for &counter in self.counters.row(k_i) {
+                non_zero += (counter != C::ZERO) as usize;
+                max_counter = max(max_counter, counter);
+                sum += counter.to_u64() as u128;
+            }
+        }
+        let cells = ((self.mask + 1) * self.k_num) as f64;
+        let error_bound = (self.total as u128 * 2).div_ceil(self.width() as u128);
+        Stats {
+            width: self.width(),
+            depth: self.k_num,
+            total: self.total,
+            occupancy: non_zero as f64 / cells,
+            max: max_counter,
+            mean: sum as f64 / cells,
+            error_bound: error_bound as u64,
+        }
+    }
+
+    /// Selects between conservative update (the default), which only
+    /// raises the lowest counters of a key, and the standard update, which
+    /// adds to every row.
+    ///
+    /// Conservative update overestimates less, but each row is no longer
+    /// the sum of the updates, so merged sketches are biased compared to a
+    /// single sketch fed with both streams.
+    pub fn set_conservative_update(&mut self, conservative: bool) {
+        self.conservative = conservative;
+    }
+
+    /// Exempts the cells of `key` from `reset()` and `reset_next()`.
+    ///
+    /// Keys are remembered by hash, so pins are dropped by `clear()`,
+    /// which reseeds the hashers.
+    pub fn pin<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        if !self.pinned.contains(&hashes) {
+            self.pinned.push(hashes);
+        }
+    }
+
+    pub fn unpin<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        N: NormalizeKey<Q>,
+    {
+        let hashes = self.normalized_key_hashes(key);
+        self.pinned.retain(|h| *h != hashes);
+    }
+
+    pub fn clear_pins(&mut self) {
+        self.pinned.clear();
+    }
+
+    fn is_pinned(&self, k_i: usize, offset: usize) -> bool {
+        self.pinned
+            .iter()
+            .any(|h| self.row_offset(h, k_i) == offset)
+    }
+
+    /// Zeroes the counters, keeping the hash keys.
+    ///
+    /// Pinned keys and cached hashes remain valid, and are kept. The
+    /// reservoir sample is emptied.
+    pub fn clear_keep_seeds(&mut self) {
+        for k_i in 0..self.k_num {
+            self.counters.row_mut(k_i).fill(C::ZERO);
+        }
+        self.reset_idx = 0;
+        self.total = 0;
+        self.inserts_since_decay = 0;
+        self.rejected_updates = 0;
+        self.saturated_updates = 0;
+        self.updated_at = Duration::ZERO;
+        #[cfg(feature = "std")]
+        if let Some(reservoir) = &mut self.reservoir {
+            reservoir.clear();
+        }
+        if let Some(heavy_hitters) = &mut self.heavy_hitters {
+            heavy_hitters.clear();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let rounding = self.rounding;
+        self.map_rows(|row| C::halve_slice(row, rounding));
+        self.inserts_since_decay = 0;
+        self.reset_idx = 0;
+        self.total = self.rounding.halve(self.total);
+    }
+
+    /// Multiplies every counter, except pinned ones, by `factor`, which
+    /// must be in `[0, 1]`, rounding with the rounding mode.
+    ///
+    /// `decay(0.5)` is the same as `reset()`, for counters up to 2^53;
+    /// larger ones are scaled with the precision of `f64`.
+    pub fn decay(&mut self, factor: f64) -> Result<(), CmsError> {
+        if !(0.0..=1.0).contains(&factor) {
+            return Err(CmsError::DecayFactor(factor));
+        }
+        let rounding = self.rounding;
+        self.map_counters(|value| rounding.scale(value, factor));
+        self.reset_idx = 0;
+        self.total = self.rounding.scale(self.total, factor);
+        Ok(())
+    }
+
+    /// Zeroes every counter, except pinned ones, below `threshold`, to drop
+    /// the noise of rare keys before exporting the sketch with
+    /// `to_sparse_bytes()`.
+    ///
+    /// Keys whose estimate was below `threshold` may then be estimated as
+    /// 0. `total()` is left unchanged.
+    pub fn prune(&mut self, threshold: C) {
+        self.map_rows(|row| {
+            for counter in row {
+                if *counter < threshold {
+                    *counter = C::ZERO;
+                }
+            }
+        })
+    }
+
+    fn map_counters<F>(&mut self, f: F)
+    where
+        F: Fn(u64) -> u64,
+    {
+        self.map_rows(|row| {
+            for counter in row {
+                *counter = C::from_u64(f(counter.to_u64())).unwrap_or(*counter);
+            }
+        })
+    }
+
+    // Applies `f` to whole rows, then restores the counters of pinned keys
+    fn map_rows<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut [C]),
+    {
+        for k_i in 0..self.k_num {
+            let pinned: Vec<(usize, C)> = self
+                .pinned
+                .iter()
+                .map(|h| {
+                    let offset = self.row_offset(h, k_i);
+                    (offset, self.counters.row(k_i)[offset])
+                })
+                .collect();
+            let row = self.counters.row_mut(k_i);
+            f(row);
+            for (offset, value) in pinned {
+                row[offset] = value;
+            }
+        }
+    }
+
+    pub fn reset_next(&mut self) -> Option<usize> {
+        let idx = self.reset_idx;
+        for k_i in 0..self.k_num {
+            if !self.is_pinned(k_i, idx) {
+                let counter = &mut self.counters.row_mut(k_i)[idx];
+                *counter = C::from_u64(self.rounding.halve(counter.to_u64())).unwrap_or(*counter);
+            }
+        }
+        let next = idx.wrapping_add(1) & self.mask;
+        self.reset_idx = next;
+        if next != 0 {
+            Some(next)
+        } else {
+            self.total = self.rounding.halve(self.total);
+            None
+        }
+    }
+
+    /// Same as calling `reset_next()` up to `n` times, stopping at the end
+    /// of the pass, so that background aging can work in larger chunks.
+    ///
+    /// Returns the next column to decay, or `None` once the pass is
+    /// complete.
+    pub fn reset_next_n(&mut self, n: usize) -> Option<usize> {
+        let mut next = Some(self.reset_idx);
+        for _ in 0..n {
+            next = self.reset_next();
+            if next.is_none() {
+                break;
+            }
+        }
+        next
+    }
+
+    fn dimensions(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+    ) -> Result<(usize, usize), CmsError> {
+        let width = Self::optimal_width(capacity, tolerance)?;
+        let k_num = Self::optimal_depth(probability)?;
+        Self::check_dimensions(width, k_num)?;
+        Ok((width, k_num))
+    }
+
+    fn check_dimensions(width: usize, k_num: usize) -> Result<(), CmsError> {
+        if width < 2 || !width.is_power_of_two() {
+            return Err(CmsError::Width(width as u64));
+        }
+        if k_num == 0 {
+            return Err(CmsError::Depth(0));
+        }
+        width
+            .checked_mul(k_num)
+            .and_then(|len| len.checked_mul(mem::size_of::<C>()))
+            .filter(|&size| size <= isize::MAX as usize)
+            .ok_or(CmsError::TooLarge)?;
+        Ok(())
+    }
+
+    fn optimal_width(capacity: usize, tolerance: f64) -> Result<usize, CmsError> {
+        if capacity == 0 {
+            return Err(CmsError::Capacity(capacity));
+        }
+        if !(tolerance > 0.0 && tolerance.is_finite()) {
+            return Err(CmsError::Tolerance(tolerance));
+        }
+        let e = tolerance / (capacity as f64);
+        #[cfg(feature = "std")]
+        let width = (2.0 / e).round() as usize;
+        #[cfg(not(feature = "std"))]
+        let width = (2.0 / e + 0.5) as usize;
+        max(2, width)
+            .checked_next_power_of_two()
+            .ok_or(CmsError::TooLarge)
+    }
+
+    // `width` is always a power of two >= 2, see `optimal_width()`
+    fn mask(width: usize) -> usize {
+        width - 1
+    }
+
+    fn optimal_depth(probability: f64) -> Result<usize, CmsError> {
+        if !(probability > 0.0 && probability < 1.0) {
+            return Err(CmsError::Probability(probability));
+        }
+        Ok(Self::optimal_k_num(probability))
+    }
+
+    #[cfg(feature = "std")]
+    fn optimal_k_num(probability: f64) -> usize {
+        max(1, ((1.0 - probability).ln() / 0.5f64.ln()) as usize)
+    }
+
+    // floor(log2(1 / (1 - probability))), without `f64::ln()`
+    #[cfg(not(feature = "std"))]
+    fn optimal_k_num(probability: f64) -> usize {
+        let mut x = 1.0 - probability;
+        let mut k_num = 0;
+        while x * 2.0 <= 1.0 {
+            x *= 2.0;
+            k_num += 1;
+        }
+        max(1, k_num)
+    }
+
+    #[cfg(feature = "std")]
+    fn sip_new() -> FastHasher {
+        Self::sip_new_with(&mut rand::thread_rng())
+    }
+
+    fn sip_new_with<R>(rng: &mut R) -> FastHasher
+    where
+        R: RngCore + ?Sized,
+    {
+        FastHasher::new_with_keys(rng.next_u64(), rng.next_u64())
+    }
+
+    fn key_hashes<Q>(&self, key: &Q) -> [u64; 2]
+    where
+        Q: Hash + ?Sized,
+    {
+        self.hashes_with(&NoNormalizer, key)
+    }
+
+    fn normalized_key_hashes<Q>(&self, key: &Q) -> [u64; 2]
+    where
+        Q: ?Sized,
+        N: NormalizeKey<Q>,
+    {
+        self.hashes_with(&self.normalizer, key)
+    }
+
+    fn hashes_with<Q, M>(&self, normalizer: &M, key: &Q) -> [u64; 2]
+    where
+        Q: ?Sized,
+        M: NormalizeKey<Q>,
+    {
+        let n = min(2, self.k_num);
+        let mut sips = [
+            self.hashers[0].build_hasher(),
+            self.hashers[1].build_hasher(),
+        ];
+        normalizer.hash_into(key, &mut sips[..n]);
+        let mut hashes = [0u64, 0u64];
+        for (hash, sip) in hashes.iter_mut().zip(&sips[..n]) {
+            *hash = sip.finish();
+        }
+        hashes
+    }
+
+    fn cached_key_hashes(&mut self, key: &K) -> [u64; 2]
+    where
+        K: Eq + Clone,
+        N: NormalizeKey<K>,
+    {
+        if let Some(hashes) = self.hash_cache.as_ref().and_then(|c| c.get(key)) {
+            return hashes;
+        }
+        let hashes = self.normalized_key_hashes(key);
+        if let Some(hash_cache) = &mut self.hash_cache {
+            hash_cache.insert(key.clone(), hashes);
+        }
+        hashes
+    }
+
+    #[inline]
+    fn row_offset(&self, hashes: &[u64; 2], k_i: usize) -> usize {
+        row_offset(hashes, k_i, self.mask)
+    }
+
+    // Hints the CPU to load the cells of `hashes`; a no-op on other targets
+    #[inline]
+    fn prefetch(&self, hashes: &[u64; 2]) {
+        #[cfg(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            target_feature = "sse"
+        ))]
+        for k_i in 0..self.k_num {
+            #[cfg(target_arch = "x86")]
+            use core::arch::x86::{_mm_prefetch, _MM_HINT_T0};
+            #[cfg(target_arch = "x86_64")]
+            use core::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+            let cell = &self.counters.row(k_i)[self.row_offset(hashes, k_i)];
+            // Prefetching has no side effects, and `cell` is a valid reference
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(cell as *const C as *const i8) };
+        }
+        #[cfg(not(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            target_feature = "sse"
+        )))]
+        let _ = hashes;
+    }
+}
+
+impl<K, C, S, N> CountMinSketch<K, C, S, N>
+where
+    K: Hash,
+    C: Counter,
+    S: Storage<C>,
+{
+    pub fn config(&self) -> SketchConfig {
+        let (k0, k1) = self.hashers[0].keys();
+        let (k2, k3) = self.hashers[1].keys();
+        SketchConfig {
+            width: (self.mask + 1) as u64,
+            depth: self.k_num as u64,
+            seeds: [k0, k1, k2, k3],
+            counter_size: mem::size_of::<C>() as u8,
+            ceiling: self.ceiling.to_u64(),
+            conservative: self.conservative,
+            rounding: self.rounding,
+            overflow_policy: self.overflow_policy,
+            decay_policy: self.decay_policy,
+        }
+    }
+
+    fn apply_policies(&mut self, config: &SketchConfig) {
+        self.conservative = config.conservative;
+        self.rounding = config.rounding;
+        self.overflow_policy = config.overflow_policy;
+        self.decay_policy = config.decay_policy;
+    }
+
+    /// Copies the counters, along with the dimensions and hash keys.
+    pub fn export_matrix(&self) -> CounterMatrix {
+        CounterMatrix {
+            width: self.width(),
+            depth: self.k_num,
+            seeds: self.config().seeds,
+            rows: self
+                .rows()
+                .map(|row| row.iter().map(|c| c.to_u64()).collect())
+                .collect(),
+        }
+    }
+
+    fn write_header(&self, magic: &[u8; 4], bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.header(magic));
+    }
+
+    fn header(&self, magic: &[u8; 4]) -> [u8; SKETCH_HEADER_LEN] {
+        let mut header = [0u8; SKETCH_HEADER_LEN];
+        header[..4].copy_from_slice(magic);
+        header[4] = SKETCH_VERSION;
+        self.config().write_to(&mut header[5..5 + CONFIG_LEN]);
+        let state = [
+            self.total,
+            self.reset_idx as u64,
+            self.updated_at.as_nanos() as u64,
+        ];
+        for (le, field) in header[5 + CONFIG_LEN..].chunks_exact_mut(8).zip(&state) {
+            le.copy_from_slice(&field.to_le_bytes());
+        }
+        header
+    }
+
+    /// Size of the output of `to_bytes()`.
+    pub fn serialized_len(&self) -> usize {
+        SKETCH_HEADER_LEN + (self.mask + 1) * self.k_num * mem::size_of::<C>()
+    }
+
+    /// Same as `to_bytes()`, writing into `out` without allocating, for
+    /// example to checkpoint a sketch to flash. Returns the number of bytes
+    /// written, `serialized_len()`.
+    ///
+    /// The output has a fixed-size header followed by the raw counters, so
+    /// it can also be embedded in a `postcard` or `bincode` message as a
+    /// byte slice, and read back with `from_bytes()` or `EmbeddedSketch`.
+    pub fn write_bytes(&self, out: &mut [u8]) -> Result<usize, CmsError> {
+        let len = self.serialized_len();
+        if out.len() < len {
+            return Err(CmsError::BufferTooSmall { needed: len });
+        }
+        out[..SKETCH_HEADER_LEN].copy_from_slice(&self.header(&SKETCH_MAGIC));
+        let counter_size = mem::size_of::<C>();
+        let mut les = out[SKETCH_HEADER_LEN..len].chunks_exact_mut(counter_size);
+        for k_i in 0..self.k_num {
+            for (counter, le) in self.counters.row(k_i).iter().zip(&mut les) {
+                le.copy_from_slice(counter.to_le_bytes().as_ref());
+            }
+        }
+        Ok(len)
+    }
+
+    /// Serializes the sketch in a versioned little-endian format, readable
+    /// by `from_bytes()` in any release supporting the same version:
+    ///
+    /// | Bytes | Content |
+    /// |-------|---------|
+    /// | 4     | Magic, `CMSK` |
+    /// | 1     | Format version, currently 2 |
+    /// | 74    | `SketchConfig::to_bytes()` |
+    /// | 8     | Total of the added values, `u64` |
+    /// | 8     | Next column for `reset_next()`, `u64` |
+    /// | 8     | `updated_at` in nanoseconds, `u64` |
+    /// | rest  | Counters, row after row, `counter_size` bytes each |
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let width = self.mask + 1;
+        let mut bytes =
+            Vec::with_capacity(SKETCH_HEADER_LEN + width * self.k_num * mem::size_of::<C>());
+        self.write_header(&SKETCH_MAGIC, &mut bytes);
+        for k_i in 0..self.k_num {
+            for counter in self.counters.row(k_i) {
+                bytes.extend_from_slice(counter.to_le_bytes().as_ref());
+            }
+        }
+        bytes
+    }
+
+    /// Same as `to_bytes()`, encoding the counters on up to `threads`
+    /// threads.
+    ///
+    /// Rows have a fixed encoded size, so each thread writes its own
+    /// segment of the output in place. Work is split by rows, so at most
+    /// `depth` threads are used.
+    #[cfg(feature = "std")]
+    pub fn to_bytes_parallel(&self, threads: usize) -> Vec<u8>
+    where
+        S: Sync,
+    {
+        let width = self.mask + 1;
+        let counter_size = mem::size_of::<C>();
+        let mut bytes = Vec::with_capacity(SKETCH_HEADER_LEN + width * self.k_num * counter_size);
+        self.write_header(&SKETCH_MAGIC, &mut bytes);
+        bytes.resize(SKETCH_HEADER_LEN + width * self.k_num * counter_size, 0);
+        let threads = threads.clamp(1, self.k_num);
+        let rows_per_thread = self.k_num.div_ceil(threads);
+        let (counters, k_num) = (&self.counters, self.k_num);
+        std::thread::scope(|scope| {
+            for (i, segment) in bytes[SKETCH_HEADER_LEN..]
+                .chunks_mut(rows_per_thread * width * counter_size)
+                .enumerate()
+            {
+                scope.spawn(move || {
+                    let mut les = segment.chunks_exact_mut(counter_size);
+                    let first = i * rows_per_thread;
+                    for k_i in first..min(first + rows_per_thread, k_num) {
+                        for (counter, le) in counters.row(k_i).iter().zip(&mut les) {
+                            le.copy_from_slice(counter.to_le_bytes().as_ref());
+                        }
+                    }
+                });
+            }
+        });
+        bytes
+    }
+
+    /// Same as `to_bytes()`, with runs of zero counters skipped, for sketches
+    /// that are still mostly empty. `from_bytes()` restores both formats.
+    ///
+    /// Counters are written row after row as pairs of runs: the number of
+    /// zero counters, then the number of nonzero counters followed by their
+    /// values, both lengths as LEB128 varints.
+    pub fn to_sparse_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SKETCH_HEADER_LEN);
+        self.write_header(&SPARSE_MAGIC, &mut bytes);
+        let mut counters = (0..self.k_num)
+            .flat_map(|k_i| self.counters.row(k_i).iter().copied())
+            .peekable();
+        let mut literals = Vec::new();
+        while counters.peek().is_some() {
+            let mut zeros = 0u64;
+            while counters.next_if_eq(&C::ZERO).is_some() {
+                zeros += 1;
+            }
+            literals.clear();
+            while let Some(counter) = counters.next_if(|&counter| counter != C::ZERO) {
+                literals.push(counter);
+            }
+            write_varint(&mut bytes, zeros);
+            write_varint(&mut bytes, literals.len() as u64);
+            for counter in &literals {
+                bytes.extend_from_slice(counter.to_le_bytes().as_ref());
+            }
+        }
+        bytes
+    }
+
+    /// Lossy export storing every counter as an 8-bit code on a log scale
+    /// spanning `0..=max`, `max` being the largest counter.
+    ///
+    /// Restored counters are never lower than the original ones, and
+    /// `1 + restored <= base * (1 + original) + 1` with
+    /// `base = (1 + max)^(1/255)`: about 5.6% for `max = 10^6`, 19% for
+    /// the full `u64` range. Since estimates are minimums over rows, the
+    /// same bound applies to them.
+    #[cfg(feature = "std")]
+    pub fn to_quantized_bytes(&self) -> Vec<u8> {
+        let width = self.mask + 1;
+        let mut bytes = Vec::with_capacity(SKETCH_HEADER_LEN + 8 + width * self.k_num);
+        self.write_header(&QUANTIZED_MAGIC, &mut bytes);
+        let max = (0..self.k_num)
+            .flat_map(|k_i| self.counters.row(k_i).iter().copied())
+            .fold(C::ZERO, max)
+            .to_u64();
+        bytes.extend_from_slice(&max.to_le_bytes());
+        let quantizer = LogQuantizer::new(max);
+        for k_i in 0..self.k_num {
+            for &counter in self.counters.row(k_i) {
+                bytes.push(quantizer.encode(counter.to_u64()));
+            }
+        }
+        bytes
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.to_bytes())
+    }
+
+    /// 128-bit content hash of the dimensions, hash keys and counters,
+    /// to check that replicas converged without comparing full tables.
+    pub fn digest(&self) -> u128 {
+        let mut hasher = siphasher::sip128::SipHasher13::new();
+        let config = self.config();
+        hasher.write(&config.width.to_le_bytes());
+        hasher.write(&config.depth.to_le_bytes());
+        for seed in &config.seeds {
+            hasher.write(&seed.to_le_bytes());
+        }
+        for k_i in 0..self.k_num {
+            for counter in self.counters.row(k_i) {
+                hasher.write(counter.to_le_bytes().as_ref());
+            }
+        }
+        hasher.finish128().as_u128()
+    }
+
+    /// Copies the counters, as the base of the next `delta_since()`.
+    pub fn snapshot(&self) -> Snapshot<C> {
+        Snapshot {
+            counters: self.rows().flatten().copied().collect(),
+            width: self.width(),
+            depth: self.k_num,
+            digest: self.digest(),
+        }
+    }
+
+    /// Encodes the counters that changed since `snapshot`, to bring a
+    /// replica that was in the state of `snapshot` up to date with
+    /// `apply_delta()`.
+    ///
+    /// The delta starts with the magic `CMSD`, a version byte (2), the
+    /// configuration, the digest of the snapshot as a little-endian `u128`,
+    /// then the current `total()` and `updated_at()` in nanoseconds as
+    /// `u64`s. Every changed counter follows, as the number of unchanged
+    /// counters before it (a LEB128 varint) and its new value.
+    pub fn delta_since(&self, snapshot: &Snapshot<C>) -> Result<Vec<u8>, Incompatibility> {
+        if snapshot.width != self.width() {
+            return Err(Incompatibility::Width {
+                ours: self.width(),
+                theirs: snapshot.width,
+            });
+        }
+        if snapshot.depth != self.k_num {
+            return Err(Incompatibility::Depth {
+                ours: self.k_num,
+                theirs: snapshot.depth,
+            });
+        }
+        let mut bytes = vec![0; DELTA_HEADER_LEN];
+        bytes[..4].copy_from_slice(&DELTA_MAGIC);
+        bytes[4] = DELTA_VERSION;
+        self.config().write_to(&mut bytes[5..5 + CONFIG_LEN]);
+        bytes[5 + CONFIG_LEN..DELTA_HEADER_LEN - 16]
+            .copy_from_slice(&snapshot.digest.to_le_bytes());
+        bytes[DELTA_HEADER_LEN - 16..DELTA_HEADER_LEN - 8]
+            .copy_from_slice(&self.total.to_le_bytes());
+        bytes[DELTA_HEADER_LEN - 8..]
+            .copy_from_slice(&(self.updated_at.as_nanos() as u64).to_le_bytes());
+        let mut unchanged = 0u64;
+        for (&counter, &base) in self.rows().flatten().zip(&snapshot.counters) {
+            if counter == base {
+                unchanged += 1;
+                continue;
+            }
+            write_varint(&mut bytes, unchanged);
+            bytes.extend_from_slice(counter.to_le_bytes().as_ref());
+            unchanged = 0;
+        }
+        Ok(bytes)
+    }
+
+    /// Applies a delta from `delta_since()`. The sketch must be in the state
+    /// of the snapshot the delta was computed from, which is checked with
+    /// its digest; nothing is changed if the delta doesn't apply.
+    ///
+    /// `updated_at()` moves forward to the source's, as with `merge()`.
+    pub fn apply_delta(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
+        if bytes.len() < DELTA_HEADER_LEN || bytes[..4] != DELTA_MAGIC {
+            return Err("Not a sketch delta");
+        }
+        if bytes[4] != DELTA_VERSION {
+            return Err("Unsupported delta version");
+        }
+        if SketchConfig::from_bytes(&bytes[5..5 + CONFIG_LEN])? != self.config() {
+            return Err("Delta is for a sketch with another configuration");
+        }
+        let mut digest = [0u8; 16];
+        digest.copy_from_slice(&bytes[5 + CONFIG_LEN..DELTA_HEADER_LEN - 16]);
+        if u128::from_le_bytes(digest) != self.digest() {
+            return Err("Delta doesn't apply to the current counters");
+        }
+        let width = self.mask + 1;
+        let len = width * self.k_num;
+        let counter_size = mem::size_of::<C>();
+        let mut changes = &bytes[DELTA_HEADER_LEN..];
+        let mut updates = Vec::new();
+        let mut idx = 0usize;
+        while !changes.is_empty() {
+            let unchanged = read_varint(&mut changes)?;
+            idx = usize::try_from(unchanged)
+                .ok()
+                .and_then(|unchanged| idx.checked_add(unchanged))
+                .filter(|&idx| idx < len && changes.len() >= counter_size)
+                .ok_or("Delta has an unexpected length")?;
+            let (le, rest) = changes.split_at(counter_size);
+            let mut buf = C::Bytes::default();
+            buf.as_mut().copy_from_slice(le);
+            updates.push((idx, C::from_le_bytes(buf)));
+            changes = rest;
+            idx += 1;
+        }
+        for (idx, counter) in updates {
+            self.counters.row_mut(idx / width)[idx % width] = counter;
+        }
+        self.total = u64_at(bytes, DELTA_HEADER_LEN - 16);
+        let updated_at = Duration::from_nanos(u64_at(bytes, DELTA_HEADER_LEN - 8));
+        self.updated_at = max(self.updated_at, updated_at);
+        Ok(())
+    }
+
+    /// Zeroes the counters and picks new hash keys.
+    ///
+    /// The sketch is no longer mergeable with sketches sharing its previous
+    /// seeds; use `clear_keep_seeds()` to keep it compatible.
+    #[cfg(feature = "std")]
+    pub fn clear(&mut self) {
+        self.clear_keep_seeds();
+        self.hashers = [Self::sip_new().into(), Self::sip_new().into()];
+        self.pinned.clear();
+        if let Some(hash_cache) = &mut self.hash_cache {
+            hash_cache.clear();
+        }
+    }
+}
+
+impl<K, C, N, H> CountMinSketch<K, C, VecStorage<C>, N, H>
+where
+    C: Counter,
+{
+    // `C2` must be at least as wide as `C`. A ceiling at the maximum of `C`
+    // is lifted to the maximum of `C2`.
+    fn widen<C2>(self) -> CountMinSketch<K, C2, VecStorage<C2>, N, H>
+    where
+        C2: Counter,
+    {
+        let width = self.mask + 1;
+        let mut counters = VecStorage::new(width, self.k_num);
+        for (wide, narrow) in counters
+            .as_mut_slice()
+            .iter_mut()
+            .zip(self.counters.as_slice())
+        {
+            *wide = C2::from_u64(narrow.to_u64()).unwrap_or(C2::MAX);
+        }
+        let ceiling = if self.ceiling == C::MAX {
+            C2::MAX
+        } else {
+            C2::from_u64(self.ceiling.to_u64()).unwrap_or(C2::MAX)
+        };
+        CountMinSketch {
+            counters,
+            hashers: self.hashers,
+            mask: self.mask,
+            k_num: self.k_num,
+            reset_idx: self.reset_idx,
+            total: self.total,
+            updated_at: self.updated_at,
+            ceiling,
+            rounding: self.rounding,
+            conservative: self.conservative,
+            decay_policy: self.decay_policy,
+            inserts_since_decay: self.inserts_since_decay,
+            decayed_at: self.decayed_at,
+            clock: self.clock,
+            overflow_policy: self.overflow_policy,
+            rejected_updates: self.rejected_updates,
+            overflow_handler: self.overflow_handler,
+            saturated_updates: self.saturated_updates,
+            pinned: self.pinned,
+            hash_cache: self.hash_cache,
+            #[cfg(feature = "std")]
+            reservoir: self.reservoir,
+            heavy_hitters: self.heavy_hitters.map(|h| {
+                let threshold = C2::from_u64(h.threshold().to_u64()).unwrap_or(C2::MAX);
+                h.with_threshold(threshold)
+            }),
+            normalizer: self.normalizer,
+            phantom_k: PhantomData,
+        }
+    }
+}
+
+pub type CountMinSketch8<K, S = VecStorage<u8>, N = NoNormalizer, H = SipBuildHasher> =
+    CountMinSketch<K, u8, S, N, H>;
+pub type CountMinSketch16<K, S = VecStorage<u16>, N = NoNormalizer, H = SipBuildHasher> =
+    CountMinSketch<K, u16, S, N, H>;
+pub type CountMinSketch32<K, S = VecStorage<u32>, N = NoNormalizer, H = SipBuildHasher> =
+    CountMinSketch<K, u32, S, N, H>;
+pub type CountMinSketch64<K, S = VecStorage<u64>, N = NoNormalizer, H = SipBuildHasher> =
+    CountMinSketch<K, u64, S, N, H>;
+
+// Lossless conversions to wider counters, keeping the hash keys, so that a
+// sketch can keep counting past the maximum of its original counter type.
+// Counters that already saturated stay at that maximum.
+macro_rules! widen_impl {
+    ($Narrow:ty, $Wide:ty) => {
+        impl<K, N, H> From<CountMinSketch<K, $Narrow, VecStorage<$Narrow>, N, H>>
+            for CountMinSketch<K, $Wide, VecStorage<$Wide>, N, H>
+        {
+            fn from(cms: CountMinSketch<K, $Narrow, VecStorage<$Narrow>, N, H>) -> Self {
+                cms.widen()
+            }
+        }
+    };
+}
+
+widen_impl!(u8, u16);
+widen_impl!(u8, u32);
+widen_impl!(u8, u64);
+widen_impl!(u16, u32);
+widen_impl!(u16, u64);
+widen_impl!(u32, u64);
+
+/// Same as `increment_all()`.
+impl<K, C, S, N, H> Extend<K> for CountMinSketch<K, C, S, N, H>
+where
+    K: Hash,
+    C: Counter,
+    S: Storage<C>,
+    N: NormalizeKey<K>,
+    H: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = K>>(&mut self, keys: I) {
+        self.increment_all(keys)
+    }
+}
+
+/// Same as `add_all()`.
+impl<K, C, S, N, H> Extend<(K, C)> for CountMinSketch<K, C, S, N, H>
+where
+    K: Hash,
+    C: Counter,
+    S: Storage<C>,
+    N: NormalizeKey<K>,
+    H: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, C)>>(&mut self, items: I) {
+        self.add_all(items)
+    }
+}
+
+/// Counts the keys in a sketch of `COLLECT_DEPTH` rows of
+/// `COLLECT_WIDTH` counters, with random hash keys: an estimate exceeds the
+/// true count by more than `total() / 2048` with probability below 1%.
+#[cfg(feature = "std")]
+impl<K, C> FromIterator<K> for CountMinSketch<K, C>
+where
+    K: Hash,
+    C: Counter,
+{
+    fn from_iter<I: IntoIterator<Item = K>>(keys: I) -> Self {
+        let counters = VecStorage::new(COLLECT_WIDTH, COLLECT_DEPTH);
+        let hashers = [Self::sip_new().into(), Self::sip_new().into()];
+        let mut cms = Self::with_storage(counters, hashers, COLLECT_WIDTH, COLLECT_DEPTH);
+        cms.increment_all(keys);
+        cms
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    #[test]
+    #[allow(clippy::legacy_numeric_constants)]
+    fn test_overflow() {
+        use crate::CountMinSketch8;
+
+        let mut cms = CountMinSketch8::<&str>::new(100, 0.95, 10.0).unwrap();
+        for _ in 0..300 {
+            cms.increment("key");
+        }
+        assert_eq!(cms.estimate("key"), u8::max_value());
+    }
+
+    #[test]
+    fn test_increment() {
+        use crate::CountMinSketch16;
+
+        let mut cms = CountMinSketch16::<&str>::new(100, 0.95, 10.0).unwrap();
+        for _ in 0..300 {
+            cms.increment("key");
+        }
+        assert_eq!(cms.estimate("key"), 300);
+    }
+
+    #[test]
+    fn test_conservative_weighted_add() {
+        use crate::{CountMinSketch32, Storage};
+
+        let mut cms = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        let hashes = cms.key_hashes("key");
+        for k_i in 0..cms.k_num {
+            let offset = cms.row_offset(&hashes, k_i);
+            cms.counters.row_mut(k_i)[offset] = k_i as u32;
+        }
+        cms.add("key", 5);
+        assert!(cms.estimate("key") >= 5);
+    }
+
+    #[test]
+    fn test_increment_multi() {
+        use crate::CountMinSketch64;
+
+        let mut cms = CountMinSketch64::<u64>::new(100, 0.99, 2.0).unwrap();
+        for i in 0..1_000_000 {
+            cms.increment(&(i % 100));
+        }
+        for key in 0..100 {
+            assert!(cms.estimate(&key) >= 9_000);
+        }
+        cms.reset();
+        for key in 0..100 {
+            assert!(cms.estimate(&key) < 11_000);
+        }
+    }
+
+    #[test]
+    fn test_from_buffer() {
+        use crate::CountMinSketch16;
+
+        let len = CountMinSketch16::<&str>::buffer_len(100, 0.95, 10.0).unwrap();
+        let mut buffer = vec![0xffffu16; len];
+        let mut cms =
+            CountMinSketch16::<&str, _>::from_buffer(&mut buffer, 100, 0.95, 10.0).unwrap();
+        assert_eq!(cms.estimate("key"), 0);
+        for _ in 0..300 {
+            cms.increment("key");
+        }
+        assert_eq!(cms.estimate("key"), 300);
+        assert!(
+            CountMinSketch16::<&str, _>::from_buffer(&mut buffer[..len - 1], 100, 0.95, 10.0)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_hash_cache() {
+        use crate::CountMinSketch32;
+
+        let mut cms = CountMinSketch32::<u64>::new(100, 0.95, 10.0).unwrap();
+        cms.enable_hash_cache(2);
+        for i in 0..1000u64 {
+            cms.increment_cached(&(i % 3));
+            cms.increment(&(i % 3));
+        }
+        for key in 0..3u64 {
+            assert!(cms.estimate_cached(&key) >= 666);
+            assert_eq!(cms.estimate_cached(&key), cms.estimate(&key));
+        }
+    }
+
+    #[test]
+    fn test_recommend_counter_type() {
+        use crate::{recommend_counter_type, CounterType};
+
+        assert_eq!(recommend_counter_type(100, 0.5), CounterType::U8);
+        assert_eq!(recommend_counter_type(300, 0.5), CounterType::U16);
+        assert_eq!(recommend_counter_type(1_000_000, 0.01), CounterType::U16);
+        assert_eq!(recommend_counter_type(1_000_000, 1.0), CounterType::U32);
+        assert_eq!(recommend_counter_type(u64::MAX, 0.9), CounterType::U64);
+    }
+
+    #[test]
+    fn test_probability() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        assert_eq!(cms.probability("a", 0), 0.0);
+        cms.add("a", 8);
+        assert_eq!(cms.total(), 8);
+        assert_eq!(cms.probability("a", 2), 0.9);
+        assert_eq!(cms.probability("b", 2), 0.1);
+    }
+
+    #[test]
+    fn test_conditional_probability() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        let mut words = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut pairs =
+            CountMinSketch32::<(&str, &str)>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        for (a, b) in [
+            ("new", "york"),
+            ("new", "york"),
+            ("new", "car"),
+            ("old", "car"),
+        ] {
+            words.increment(a);
+            pairs.increment(&(a, b));
+        }
+        let p = words
+            .conditional_probability(&pairs, &"new", &"york")
+            .unwrap();
+        assert!((p - 2.0 / 3.0).abs() < 1e-9);
+        assert!(words
+            .conditional_probability(&pairs, &"blue", &"car")
+            .is_err());
+    }
+
+    #[test]
+    fn test_pmi() {
+        use crate::{CmsError, CountMinSketch32};
+
+        let seeds = [1, 2, 3, 4];
+        let mut items = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut pairs =
+            CountMinSketch32::<(&str, &str)>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        for (a, b) in [("x", "y"), ("x", "y"), ("z", "w"), ("z", "w")] {
+            items.increment(a);
+            items.increment(b);
+            pairs.increment(&(a, b));
+        }
+        let pmi = items.pmi(&pairs, &"x", &"y", 1).unwrap();
+        assert!((pmi - 3.0).abs() < 1e-9);
+        assert_eq!(items.pmi(&pairs, &"x", &"y", 3), Err(CmsError::CountTooLow));
+    }
+
+    #[test]
+    fn test_invalid_dimensions() {
+        use crate::CountMinSketch8;
+
+        assert!(CountMinSketch8::<&str>::new(100, 0.95, 0.0).is_err());
+        assert!(CountMinSketch8::<&str>::new(usize::MAX, 0.95, 1e-300).is_err());
+        assert!(CountMinSketch8::<&str>::estimate_memory(100, 0.95, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        use crate::{CmsError, CountMinSketch32};
+
+        let new = CountMinSketch32::<&str>::new;
+        assert_eq!(new(0, 0.95, 10.0).err(), Some(CmsError::Capacity(0)));
+        assert_eq!(new(100, 1.5, 10.0).err(), Some(CmsError::Probability(1.5)));
+        assert_eq!(new(100, 0.0, 10.0).err(), Some(CmsError::Probability(0.0)));
+        assert!(matches!(
+            new(100, f64::NAN, 10.0),
+            Err(CmsError::Probability(_))
+        ));
+        assert_eq!(new(100, 0.95, 0.0).err(), Some(CmsError::Tolerance(0.0)));
+        assert_eq!(
+            new(100, 0.95, f64::INFINITY).err(),
+            Some(CmsError::Tolerance(f64::INFINITY))
+        );
+        assert_eq!(
+            new(usize::MAX, 0.95, 1e-300).err(),
+            Some(CmsError::TooLarge)
+        );
+        let mut buffer = [0u32; 4];
+        assert!(matches!(
+            crate::CountMinSketch::from_buffer(&mut buffer[..], 100, 0.95, 10.0)
+                .map(|cms: CountMinSketch32<&str, _>| cms.total()),
+            Err(CmsError::BufferTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sketch2d() {
+        use crate::CountMinSketch2D;
+
+        let mut cms = CountMinSketch2D::<u32, u32>::new(1000, 0.95, 10.0).unwrap();
+        for user in 0..10u32 {
+            for item in 0..10u32 {
+                cms.add(&user, &item, (user * item) as u64);
+            }
+        }
+        assert!(cms.estimate(&3, &4) >= 12);
+        assert!(cms.estimate_row(&3) >= 135);
+        assert_eq!(cms.total(), 2025);
+    }
+
+    #[test]
+    fn test_simulate_accuracy() {
+        use crate::{simulate_accuracy, SketchParams, StreamModel};
+
+        let params = SketchParams {
+            capacity: 10_000,
+            probability: 0.99,
+            tolerance: 10.0,
+        };
+        let stream_model = StreamModel {
+            distinct_keys: 1000,
+            length: 10_000,
+            zipf_exponent: 1.1,
+            seed: 42,
+        };
+        let report = simulate_accuracy(params, stream_model).unwrap();
+        assert!(report.p50 <= report.p90 && report.p90 <= report.p99 && report.p99 <= report.max);
+        assert!(report.p90 <= 10);
+    }
+
+    #[test]
+    fn test_pinned_keys() {
+        use crate::CountMinSketch32;
+
+        let mut cms = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        cms.add("critical", 100);
+        cms.pin("critical");
+        cms.reset();
+        assert_eq!(cms.estimate("critical"), 100);
+        while cms.reset_next().is_some() {}
+        assert_eq!(cms.estimate("critical"), 100);
+        cms.unpin("critical");
+        cms.reset();
+        assert_eq!(cms.estimate("critical"), 50);
+    }
+
+    #[test]
+    fn test_ceiling() {
+        use crate::CountMinSketch32;
+
+        let mut cms = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        cms.add("a", 5000);
+        cms.set_ceiling(1000);
+        assert_eq!(cms.estimate("a"), 1000);
+        cms.add("b", 999);
+        cms.add("b", 999);
+        assert_eq!(cms.estimate("b"), 1000);
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        use crate::{Clock, ManualClock};
+        use std::time::Duration;
+
+        let clock = ManualClock::new(Duration::from_secs(10));
+        let by_ref = &clock;
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(by_ref.now(), Duration::from_millis(11_500));
+        clock.set(Duration::ZERO);
+        assert_eq!(by_ref.now(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_config() {
+        use crate::{
+            CountMinSketch16, CountMinSketch32, DecayPolicy, OverflowPolicy, Rounding, SketchConfig,
+        };
+        use std::time::Duration;
+
+        let mut a = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        a.set_ceiling(1000);
+        a.set_conservative_update(false);
+        a.set_rounding(Rounding::KeepNonZero);
+        a.set_overflow_policy(OverflowPolicy::Error);
+        a.set_decay_policy(DecayPolicy::EveryNInserts(500), Duration::ZERO);
+        let bytes = a.config().to_bytes();
+        let config = SketchConfig::from_bytes(&bytes).unwrap();
+        assert_eq!(config, a.config());
+        let mut b = CountMinSketch32::<&str>::from_config(&config).unwrap();
+        assert_eq!(b.ceiling(), 1000);
+        assert!(!b.conservative_update());
+        assert_eq!(b.rounding(), Rounding::KeepNonZero);
+        assert_eq!(b.overflow_policy(), OverflowPolicy::Error);
+        assert_eq!(b.decay_policy(), DecayPolicy::EveryNInserts(500));
+        assert_eq!(b.config(), a.config());
+        a.add("key", 42);
+        b.add("key", 42);
+        assert_eq!(a.estimate("key"), b.estimate("key"));
+        assert!(CountMinSketch16::<&str>::from_config(&config).is_err());
+        assert!(SketchConfig::from_bytes(&bytes[1..]).is_err());
+
+        let mut invalid = bytes.clone();
+        invalid[63] = 3;
+        assert_eq!(
+            SketchConfig::from_bytes(&invalid),
+            Err("Unknown rounding policy")
+        );
+
+        // Version 1 has no policies, and gets the defaults
+        let mut v1 = bytes[..62].to_vec();
+        v1[4] = 1;
+        let config = SketchConfig::from_bytes(&v1).unwrap();
+        assert_eq!(config.seeds, a.config().seeds);
+        assert!(config.conservative);
+        assert_eq!(config.rounding, Rounding::Down);
+        assert_eq!(config.overflow_policy, OverflowPolicy::Saturate);
+        assert_eq!(config.decay_policy, DecayPolicy::Never);
+        assert!(SketchConfig::from_bytes(&bytes[..62]).is_err());
+    }
+
+    #[test]
+    fn test_is_mergeable() {
+        use crate::{CountMinSketch32, Incompatibility};
+
+        let a = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        let b = CountMinSketch32::<&str>::from_config(&a.config()).unwrap();
+        assert_eq!(a.is_mergeable(&b), Ok(()));
+        let c = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        assert_eq!(a.is_mergeable(&c), Err(Incompatibility::Seeds));
+        let d = CountMinSketch32::<&str>::new(100, 0.99, 10.0).unwrap();
+        assert!(matches!(
+            a.is_mergeable(&d),
+            Err(Incompatibility::Depth { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sketch_handle() {
+        use crate::{CountMinSketch64, SketchHandle};
+        use std::thread;
+
+        let cms = CountMinSketch64::<u32>::new(100, 0.95, 10.0).unwrap();
+        let handle = SketchHandle::spawn(cms);
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        handle.increment(7);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(handle.estimate(7).wait(), Some(400));
+    }
+
+    #[test]
+    fn test_estimate_decayed() {
+        use crate::{CountMinSketch32, ManualClock};
+        use std::time::Duration;
+
+        let clock = ManualClock::new(Duration::from_secs(100));
+        let mut cms = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        cms.add_at("key", 80, &clock);
+        let half_life = Duration::from_secs(10);
+        assert_eq!(cms.estimate_decayed("key", half_life, &clock), 80.0);
+        clock.advance(Duration::from_secs(20));
+        assert!((cms.estimate_decayed("key", half_life, &clock) - 20.0).abs() < 1e-9);
+        assert_eq!(cms.estimate("key"), 80);
+    }
+
+    #[test]
+    fn test_base64() {
+        use crate::base64;
+        use crate::CountMinSketch16;
+
+        for len in 0..8 {
+            let bytes: Vec<u8> = (0..len).map(|i| i * 37 + 1).collect();
+            assert_eq!(base64::decode(&base64::encode(&bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base64::encode(b"foob"), "Zm9vYg==");
+        assert!(base64::decode("Zm9vYg=").is_err());
+        assert!(base64::decode("Zm9v!g==").is_err());
+
+        let mut cms = CountMinSketch16::<&str>::new(100, 0.95, 10.0).unwrap();
+        cms.add("key", 1234);
+        let restored = CountMinSketch16::<&str>::from_base64(&cms.to_base64()).unwrap();
+        assert_eq!(restored.estimate("key"), 1234);
+        assert_eq!(restored.total(), 1234);
+        assert_eq!(restored.to_bytes(), cms.to_bytes());
+    }
+
+    #[test]
+    fn test_flow_key() {
+        use crate::{CountMinSketch32, FlowKey};
+        use std::net::{IpAddr, Ipv4Addr};
+
+        let v4 = FlowKey::new(
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            40000,
+            443,
+            6,
+        );
+        let mapped = FlowKey {
+            src: IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped()),
+            dst: IpAddr::V6(Ipv4Addr::new(10, 0, 0, 2).to_ipv6_mapped()),
+            ..v4
+        };
+        assert_ne!(v4.to_bytes(), mapped.to_bytes());
+        assert_eq!(&v4.to_bytes()[33..], &[0x9c, 0x40, 0x01, 0xbb, 6]);
+
+        let mut cms = CountMinSketch32::<FlowKey>::new(100, 0.95, 10.0).unwrap();
+        for _ in 0..10 {
+            cms.increment(&v4);
+        }
+        assert_eq!(cms.estimate(&v4), 10);
+    }
+
+    #[test]
+    fn test_tiny_lfu_admission() {
+        use crate::TinyLfuAdmission;
+
+        let mut admission = TinyLfuAdmission::<u32>::new(100).unwrap();
+        for _ in 0..20 {
+            admission.record_access(&1);
+        }
+        admission.record_access(&2);
+        assert_eq!(admission.frequency(&1), 15);
+        assert!(admission.admit(&1, &2));
+        assert!(!admission.admit(&2, &1));
+        for i in 0..1000 {
+            admission.record_access(&(1000 + i));
+        }
+        assert!(admission.frequency(&1) < 15);
+    }
+
+    #[test]
+    fn test_critical_section_sketch() {
+        use crate::{CountMinSketch16, CriticalSection, CriticalSectionSketch};
+        use std::cell::Cell;
+        use std::panic::{self, AssertUnwindSafe};
+        use std::sync::Mutex;
+        use std::thread;
+
+        static LOCK: Mutex<()> = Mutex::new(());
+        thread_local!(static DEPTH: Cell<usize> = const { Cell::new(0) });
+
+        // Nestable, like interrupt masking
+        struct TestSection;
+
+        unsafe impl CriticalSection for TestSection {
+            fn with<R>(f: impl FnOnce() -> R) -> R {
+                let _guard = match DEPTH.with(|depth| depth.replace(depth.get() + 1)) {
+                    0 => Some(LOCK.lock().unwrap()),
+                    _ => None,
+                };
+                let res = f();
+                DEPTH.with(|depth| depth.set(depth.get() - 1));
+                res
+            }
+        }
+
+        let cms = CountMinSketch16::<u8>::new(100, 0.95, 10.0).unwrap();
+        let shared = CriticalSectionSketch::<_, TestSection>::new(cms);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        shared.with(|cms| cms.increment(&1)).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(shared.with(|cms| cms.estimate(&1)), Some(400));
+        assert_eq!(shared.with(|_| shared.with(|_| ())), Some(None));
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            shared.with(|_| panic!("update failed"));
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(shared.with(|cms| cms.estimate(&1)), Some(400));
+    }
+
+    #[test]
+    fn test_quantized_export() {
+        use crate::CountMinSketch32;
+
+        let mut cms = CountMinSketch32::<u32>::new(1000, 0.95, 10.0).unwrap();
+        for key in 0..1000u32 {
+            cms.add(&key, key * key);
+        }
+        let bytes = cms.to_quantized_bytes();
+        assert!(bytes.len() < cms.to_bytes().len() / 3);
+        let restored = CountMinSketch32::<u32>::from_quantized_bytes(&bytes).unwrap();
+        let max = cms.counters.as_slice().iter().copied().max().unwrap();
+        let base = (1.0 + max as f64).powf(1.0 / 255.0);
+        for key in 0..1000u32 {
+            let (original, lossy) = (cms.estimate(&key) as f64, restored.estimate(&key) as f64);
+            assert!(lossy >= original);
+            assert!(1.0 + lossy <= base * (1.0 + original) + 1.0);
+        }
+        assert!(CountMinSketch32::<u32>::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_digest() {
+        use crate::CountMinSketch32;
+
+        let mut a = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        let mut b = CountMinSketch32::<&str>::from_config(&a.config()).unwrap();
+        a.add("x", 3);
+        b.add("x", 3);
+        assert_eq!(a.digest(), b.digest());
+        b.increment("y");
+        assert_ne!(a.digest(), b.digest());
+        let c = CountMinSketch32::<&str>::from_config(&a.config()).unwrap();
+        let d = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        assert_ne!(c.digest(), d.digest());
+    }
+
+    #[test]
+    fn test_crdt_join() {
+        use crate::{CountMinSketch64, CrdtError, CrdtSketch};
+
+        let config = CountMinSketch64::<&str>::new_with_keys(100, 0.95, 10.0, [1, 2, 3, 4])
+            .unwrap()
+            .config();
+        let mut a = CrdtSketch::<&str>::new(1, &config).unwrap();
+        let mut b = CrdtSketch::<&str>::new(2, &config).unwrap();
+        a.add("x", 5);
+        b.add("x", 3);
+        b.add("y", 7);
+        let mut ab = CrdtSketch::<&str>::new(3, &config).unwrap();
+        ab.join(&a).unwrap();
+        ab.join(&b).unwrap();
+        let mut ba = CrdtSketch::<&str>::new(4, &config).unwrap();
+        ba.join(&b).unwrap();
+        ba.join(&a).unwrap();
+        ba.join(&a).unwrap();
+        assert_eq!(ab.sketch().digest(), ba.sketch().digest());
+        assert_eq!(ab.replicas(), ba.replicas());
+        assert_eq!(ab.estimate("x"), 8);
+        assert_eq!(ab.estimate("y"), 7);
+        assert_eq!(ab.sketch().total(), 15);
+
+        a.add("x", 1);
+        ab.join(&a).unwrap();
+        ba.join(&ab).unwrap();
+        assert_eq!(ab.estimate("x"), 9);
+        assert_eq!(ba.sketch().digest(), ab.sketch().digest());
+        assert_eq!(ba.sketch().total(), 16);
+
+        let mut impostor = CrdtSketch::<&str>::new(1, &config).unwrap();
+        impostor.add("z", 1);
+        impostor.add("z", 1);
+        assert_eq!(
+            ab.join(&impostor),
+            Err(CrdtError::Conflict { replica_id: 1 })
+        );
+    }
+
+    #[test]
+    fn test_key_normalizer() {
+        use crate::{CountMinSketch32, Lowercase, Trim};
+        use std::borrow::Cow;
+
+        let mut cms = CountMinSketch32::<String>::new(100, 0.95, 10.0)
+            .unwrap()
+            .with_normalizer((Trim, Lowercase));
+        cms.increment("Example.COM ");
+        cms.increment("example.com");
+        cms.increment(&" EXAMPLE.com".to_string());
+        assert_eq!(cms.estimate("example.com"), 3);
+
+        fn strip_www(key: &str) -> Cow<'_, str> {
+            Cow::Borrowed(key.strip_prefix("www.").unwrap_or(key))
+        }
+        let mut cms = CountMinSketch32::<&str>::new(100, 0.95, 10.0)
+            .unwrap()
+            .with_normalizer(strip_www);
+        cms.increment("www.example.com");
+        assert_eq!(cms.estimate("example.com"), 1);
+    }
+
+    #[test]
+    fn test_ecm_sketch() {
+        use crate::EcmSketch;
+        use std::time::Duration;
+
+        let window = Duration::from_secs(60);
+        let seeds = [1, 2, 3, 4];
+        let mut ecm =
+            EcmSketch::<&str>::new_with_keys(100, 0.95, 10.0, window, 0.1, seeds).unwrap();
+        for second in 0..120 {
+            ecm.add("key", 10, Duration::from_secs(second));
+        }
+        let estimate = ecm.estimate("key", Duration::from_secs(119));
+        assert!((540..=660).contains(&estimate), "{}", estimate);
+        assert_eq!(ecm.estimate("key", Duration::from_secs(200)), 0);
+        assert_eq!(ecm.estimate("other", Duration::from_secs(119)), 0);
+
+        // Batches match the same events added one at a time
+        let mut batched =
+            EcmSketch::<&str>::new_with_keys(100, 0.95, 10.0, window, 0.1, seeds).unwrap();
+        let mut single =
+            EcmSketch::<&str>::new_with_keys(100, 0.95, 10.0, window, 0.1, seeds).unwrap();
+        for (second, value) in [(0, 37), (5, 1), (20, 100), (30, 3), (70, 64)] {
+            let now = Duration::from_secs(second);
+            batched.add("key", value, now);
+            for _ in 0..value {
+                single.increment("key", now);
+            }
+            for later in second..second + 90 {
+                let later = Duration::from_secs(later);
+                assert_eq!(
+                    batched.estimate("key", later),
+                    single.estimate("key", later)
+                );
+            }
+        }
+        batched.add("key", 1_000_000_000, Duration::from_secs(80));
+        let estimate = batched.estimate("key", Duration::from_secs(80));
+        assert!(
+            (900_000_000..=1_100_000_000).contains(&estimate),
+            "{}",
+            estimate
+        );
+    }
+
+    #[test]
+    fn test_temporal_sketch() {
+        use crate::TemporalSketch;
+        use std::time::Duration;
+
+        let hour = Duration::from_secs(3600);
+        let mut cms = TemporalSketch::<&str>::new(100, 0.95, 10.0, hour, Duration::ZERO).unwrap();
+        cms.add("key", 5, hour / 2);
+        cms.add("key", 7, hour * 3 / 2);
+        cms.add("key", 11, hour * 10);
+        assert_eq!(cms.estimate_at("key", hour / 2), 0);
+        assert_eq!(cms.estimate_at("key", hour), 5);
+        assert_eq!(cms.estimate_at("key", hour * 9), 12);
+        assert_eq!(cms.estimate("key"), 23);
+        assert_eq!(cms.checkpoints().collect::<Vec<_>>(), vec![hour, hour * 2]);
+
+        // More than 2^32 epochs in a single step
+        let ms = Duration::from_millis(1);
+        let mut cms = TemporalSketch::<&str>::new(100, 0.95, 10.0, ms, Duration::ZERO).unwrap();
+        let later = Duration::from_secs(60 * 86400);
+        cms.add("key", 1, Duration::ZERO);
+        cms.add("key", 1, later);
+        cms.add("key", 1, later + ms);
+        let checkpoints: Vec<_> = cms.checkpoints().collect();
+        assert_eq!(checkpoints, vec![ms, later + ms]);
+    }
+
+    #[test]
+    fn test_decayed_top_k() {
+        use crate::DecayedTopK;
+        use std::time::Duration;
+
+        let minute = Duration::from_secs(60);
+        let seeds = [1, 2, 3, 4];
+        let mut top =
+            DecayedTopK::<&str>::new_with_keys(100, 0.95, 10.0, 2, minute, seeds).unwrap();
+        for _ in 0..100 {
+            top.increment(&"old", Duration::ZERO);
+        }
+        for _ in 0..40 {
+            top.increment(&"recent", minute * 2);
+        }
+        top.increment(&"noise", minute * 2);
+        let keys: Vec<_> = top.top(minute * 2).iter().map(|e| *e.0).collect();
+        assert_eq!(keys, vec!["recent", "old"]);
+        let top = top.top(minute * 3);
+        assert!((top[0].1 - 20.0).abs() < 0.1);
+        assert!((top[1].1 - 12.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_is_frequent() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.add("key", 10);
+        assert!(cms.is_frequent("key", 10));
+        assert!(!cms.is_frequent("key", 11));
+        assert!(!cms.is_frequent("absent", 1));
+        assert!(cms.is_frequent("absent", 0));
+    }
+
+    #[test]
+    fn test_parallel_serialization() {
+        use crate::CountMinSketch16;
+
+        let mut cms = CountMinSketch16::<u32>::new(1000, 0.99, 10.0).unwrap();
+        for i in 0..1000 {
+            cms.add(&i, (i % 7) as u16);
+        }
+        let bytes = cms.to_bytes();
+        for threads in [0, 1, 2, 3, 64] {
+            assert_eq!(cms.to_bytes_parallel(threads), bytes);
+            let restored = CountMinSketch16::<u32>::from_bytes_parallel(&bytes, threads).unwrap();
+            assert_eq!(restored.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_clear_keep_seeds() {
+        use crate::CountMinSketch32;
+
+        let mut cms = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        let other = CountMinSketch32::<&str>::from_config(&cms.config()).unwrap();
+        cms.add("key", 3);
+        cms.clear_keep_seeds();
+        assert_eq!(cms.estimate("key"), 0);
+        assert_eq!(cms.total(), 0);
+        assert!(cms.is_mergeable(&other).is_ok());
+        cms.clear();
+        assert!(cms.is_mergeable(&other).is_err());
+    }
+
+    #[test]
+    fn test_rounding() {
+        use crate::{CountMinSketch16, Rounding};
+
+        for (rounding, expected) in [
+            (Rounding::Down, [0, 1, 0]),
+            (Rounding::HalfUp, [1, 2, 1]),
+            (Rounding::KeepNonZero, [1, 1, 1]),
+        ] {
+            let seeds = [1, 2, 3, 4];
+            let mut cms = CountMinSketch16::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+            assert_eq!(cms.rounding(), Rounding::Down);
+            cms.set_rounding(rounding);
+            cms.add("one", 1);
+            cms.add("three", 3);
+            cms.reset();
+            assert_eq!(cms.estimate("one"), expected[0]);
+            assert_eq!(cms.estimate("three"), expected[1]);
+            cms.reset();
+            assert_eq!(cms.estimate("one"), expected[2]);
+        }
+    }
+
+    #[test]
+    fn test_portable_indexing() {
+        use crate::{
+            row_index, CountMinSketch32, DecayPolicy, OverflowPolicy, Rounding, SketchConfig,
+        };
+
+        // Reference values, which must be identical on every target
+        let hashes = [0x0123_4567_89ab_cdef, 0xfedc_ba98_7654_3210];
+        let offsets: Vec<u64> = (0..6).map(|k_i| row_index(&hashes, k_i, 0xffff)).collect();
+        assert_eq!(offsets, [52719, 12816, 12815, 25631, 38447, 51263]);
+        for mask in [0x1, 0xff, 0xffff_ffff, u64::MAX] {
+            for k_i in 0..6 {
+                assert_eq!(
+                    row_index(&hashes, k_i, mask),
+                    row_index(&hashes, k_i, u64::MAX) & mask
+                );
+            }
+        }
+
+        let config = SketchConfig {
+            width: 1024,
+            depth: 5,
+            seeds: [1, 2, 3, 4],
+            counter_size: 4,
+            ceiling: u32::MAX as u64,
+            conservative: true,
+            rounding: Rounding::Down,
+            overflow_policy: OverflowPolicy::Saturate,
+            decay_policy: DecayPolicy::Never,
+        };
+        let mut cms = CountMinSketch32::<&str>::from_config(&config).unwrap();
+        cms.add("portable", 42);
+        assert_eq!(cms.digest(), 0xa71bbda59b10f70b37860a8bd5dc2eac);
+    }
+
+    #[test]
+    fn test_estimate_spread() {
+        use crate::{
+            CountMinSketch32, DecayPolicy, OverflowPolicy, Rounding, SketchConfig, Storage,
+        };
+
+        let config = SketchConfig {
+            width: 16,
+            depth: 3,
+            seeds: [1, 2, 3, 4],
+            counter_size: 4,
+            ceiling: u32::MAX as u64,
+            conservative: true,
+            rounding: Rounding::Down,
+            overflow_policy: OverflowPolicy::Saturate,
+            decay_policy: DecayPolicy::Never,
+        };
+        let mut cms = CountMinSketch32::<u32>::from_config(&config).unwrap();
+        let spread = cms.estimate_spread(&0);
+        assert_eq!((spread.min, spread.max, spread.dispersion), (0, 0, 0.0));
+        let hashes = cms.key_hashes(&0);
+        for (k_i, value) in [10, 30, 20].iter().enumerate() {
+            let offset = cms.row_offset(&hashes, k_i);
+            cms.counters.row_mut(k_i)[offset] = *value;
+        }
+        let spread = cms.estimate_spread(&0);
+        assert_eq!((spread.min, spread.median, spread.max), (10, 20, 30));
+        assert_eq!(spread.min, cms.estimate(&0));
+        assert!((spread.dispersion - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_new_with_rng() {
+        use crate::CountMinSketch16;
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut a =
+            CountMinSketch16::<&str>::new_with_rng(100, 0.95, 10.0, &mut StdRng::seed_from_u64(7))
+                .unwrap();
+        let b =
+            CountMinSketch16::<&str>::new_with_rng(100, 0.95, 10.0, &mut StdRng::seed_from_u64(7))
+                .unwrap();
+        assert_eq!(a.config(), b.config());
+        a.increment("key");
+        assert_eq!(a.estimate("key"), 1);
+    }
+
+    #[test]
+    fn test_estimate_skew() {
+        use crate::CountMinSketch64;
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let cms = CountMinSketch64::<usize>::new(1000, 0.99, 1.0).unwrap();
+        assert_eq!(cms.estimate_skew(), None);
+        for &exponent in &[0.5, 1.0, 1.5] {
+            let keys = 100_000;
+            let mut cdf = Vec::with_capacity(keys);
+            let mut sum = 0.0;
+            for rank in 1..=keys {
+                sum += 1.0 / (rank as f64).powf(exponent);
+                cdf.push(sum);
+            }
+            let mut rng = StdRng::seed_from_u64(1);
+            let mut cms = CountMinSketch64::<usize>::new(10_000, 0.99, 1.0).unwrap();
+            for _ in 0..100_000 {
+                let x = rng.gen::<f64>() * sum;
+                cms.increment(&cdf.partition_point(|&c| c < x));
+            }
+            let skew = cms.estimate_skew().unwrap();
+            assert!((skew - exponent).abs() < 0.1, "{} vs {}", skew, exponent);
+        }
+    }
+
+    #[test]
+    fn test_reservoir() {
+        use crate::CountMinSketch32;
+
+        let mut cms = CountMinSketch32::<u32>::new(1000, 0.95, 10.0).unwrap();
+        cms.increment_sampled(&0);
+        assert!(cms.sample().is_empty());
+        cms.enable_reservoir(10);
+        for i in 1..=5 {
+            cms.add_sampled(&i, 2);
+        }
+        let mut sample = cms.sample();
+        sample.sort();
+        assert_eq!(sample, vec![(&1, 2), (&2, 2), (&3, 2), (&4, 2), (&5, 2)]);
+        for i in 6..1000 {
+            cms.increment_sampled(&i);
+        }
+        let sample = cms.sample();
+        assert_eq!(sample.len(), 10);
+        assert!(sample
+            .iter()
+            .all(|&(key, count)| count >= cms.estimate(key) && *key < 1000));
+        cms.clear_keep_seeds();
+        assert!(cms.sample().is_empty());
+    }
+
+    #[test]
+    fn test_merge_from_readers() {
+        use crate::CountMinSketch16;
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountMinSketch16::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut b = CountMinSketch16::<&str>::from_config(&a.config()).unwrap();
+        a.add("x", 3);
+        b.add("x", 4);
+        b.add("y", u16::MAX);
+        let shards = [a.to_bytes(), b.to_bytes(), b.to_bytes()];
+        let merged =
+            CountMinSketch16::<&str>::merge_from_readers(shards.iter().map(|s| &s[..])).unwrap();
+        assert_eq!(merged.estimate("x"), 11);
+        assert_eq!(merged.estimate("y"), u16::MAX);
+        assert_eq!(merged.total(), 3 + 4 * 2 + u16::MAX as u64 * 2);
+
+        let other = CountMinSketch16::<&str>::new(100, 0.95, 10.0).unwrap();
+        let shards = [a.to_bytes(), other.to_bytes()];
+        assert!(
+            CountMinSketch16::<&str>::merge_from_readers(shards.iter().map(|s| &s[..])).is_err()
+        );
+        let truncated = &shards[0][..shards[0].len() - 1];
+        assert!(CountMinSketch16::<&str>::merge_from_readers(vec![truncated]).is_err());
+        assert!(CountMinSketch16::<&str>::merge_from_readers(Vec::<&[u8]>::new()).is_err());
+    }
+
+    #[test]
+    fn test_delta() {
+        use crate::{CountMinSketch32, Delta};
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.add("steady", 10);
+        cms.add("fading", 10);
+        let mut earlier = CountMinSketch32::<&str>::from_bytes(&cms.to_bytes()).unwrap();
+        earlier.add("fading", 20);
+        cms.add("steady", 1);
+        cms.add("rising", 50);
+        assert_eq!(
+            cms.delta(&earlier, "rising").unwrap(),
+            Delta {
+                current: 50,
+                previous: 0,
+                change: 50
+            }
+        );
+        let deltas = cms
+            .deltas(&earlier, ["steady", "fading"].iter().copied())
+            .unwrap();
+        assert_eq!(deltas[0].0, "steady");
+        assert_eq!(deltas[0].1.change, 1);
+        assert_eq!(deltas[1].1.change, -20);
+        let other = CountMinSketch32::<&str>::new(100, 0.95, 10.0).unwrap();
+        assert!(cms.delta(&other, "steady").is_err());
+    }
+
+    #[test]
+    fn test_embedded_sketch() {
+        use crate::{CountMinSketch16, EmbeddedSketch};
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch16::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.add("the", 1000);
+        cms.add("of", 600);
+        let bytes = cms.to_bytes();
+
+        let mut embedded = EmbeddedSketch::<&str>::new(&bytes).unwrap();
+        assert_eq!(embedded.estimate("the"), 1000);
+        assert_eq!(embedded.estimate("of"), 600);
+        assert_eq!(embedded.total(), 1600);
+        assert!(!embedded.is_owned());
+        embedded.add("the", u16::MAX as u64);
+        assert!(embedded.is_owned());
+        assert_eq!(embedded.estimate("the"), u16::MAX as u64);
+        assert_eq!(embedded.into_sketch().estimate("of"), 600);
+
+        assert!(EmbeddedSketch::<&str>::new(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        use crate::{CountMinSketch8, Incompatibility};
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountMinSketch8::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut b = CountMinSketch8::<&str>::from_config(&a.config()).unwrap();
+        a.add("x", 3);
+        a.add("y", 200);
+        b.add("x", 4);
+        b.add("y", 200);
+        b.add("z", 1);
+        a.merge(&b).unwrap();
+        assert_eq!(a.estimate("x"), 7);
+        assert_eq!(a.estimate("y"), u8::MAX);
+        assert_eq!(a.estimate("z"), 1);
+        assert_eq!(a.total(), 3 + 200 + 4 + 200 + 1);
+
+        let c = CountMinSketch8::<&str>::new(100, 0.95, 10.0).unwrap();
+        assert_eq!(a.merge(&c), Err(Incompatibility::Seeds));
+        assert_eq!(a.estimate("x"), 7);
+    }
+
+    #[test]
+    fn test_new_with_keys() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut b = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        assert_eq!(a.config().seeds, seeds);
+        a.add("x", 3);
+        b.add("x", 3);
+        assert_eq!(a.digest(), b.digest());
+        assert!(a.merge(&b).is_ok());
+    }
+
+    #[test]
+    fn test_generic_counter() {
+        use crate::{CountMinSketch, CountMinSketch16, Counter};
+
+        fn heavy<C: Counter>(cms: &CountMinSketch<&str, C>, keys: &[&'static str]) -> Vec<u64> {
+            keys.iter().map(|key| cms.estimate(key).to_u64()).collect()
+        }
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountMinSketch16::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut b = CountMinSketch::<&str, u64>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        a.add("x", 3);
+        b.add("x", 3);
+        assert_eq!(heavy(&a, &["x", "y"]), vec![3, 0]);
+        assert_eq!(heavy(&b, &["x", "y"]), vec![3, 0]);
+    }
+
+    #[test]
+    fn test_atomic_sketch() {
+        use crate::AtomicCountMinSketch32;
+
+        let cms = AtomicCountMinSketch32::<u32>::new(1000, 0.95, 10.0).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..1000u32 {
+                        cms.add(&(i % 10), 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(cms.total(), 4000);
+        for i in 0..10u32 {
+            assert!(cms.estimate(&i) >= 400);
+        }
+        let snapshot = cms.snapshot();
+        assert_eq!(snapshot.estimate(&3), cms.estimate(&3));
+        assert_eq!(snapshot.total(), 4000);
+        cms.clear();
+        assert_eq!(cms.estimate(&3), 0);
+    }
+
+    #[test]
+    fn test_custom_hasher() {
+        use crate::CountMinSketch;
+        use std::collections::hash_map::RandomState;
+
+        let hashers = [RandomState::new(), RandomState::new()];
+        let mut cms =
+            CountMinSketch::<&str, u32, _, _, _>::with_hashers(10_000, 0.95, 10.0, hashers)
+                .unwrap();
+        cms.add("x", 3);
+        cms.increment("x");
+        assert_eq!(cms.estimate("x"), 4);
+        assert_eq!(cms.estimate("y"), 0);
+        cms.clear_keep_seeds();
+        assert_eq!(cms.estimate("x"), 0);
+    }
+
+    #[test]
+    fn test_builder() {
+        use crate::{CmsError, CountMinSketch32};
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountMinSketch32::<&str>::builder()
+            .capacity(100)
+            .probability(0.95)
+            .tolerance(10.0)
+            .seeds(seeds)
+            .build()
+            .unwrap();
+        let mut b = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        a.add("x", 3);
+        b.add("x", 3);
+        assert_eq!(a.digest(), b.digest());
+
+        let mut cms = CountMinSketch32::<&str>::builder()
+            .width(1 << 10)
+            .depth(4)
+            .conservative_update(false)
+            .ceiling(5)
+            .build()
+            .unwrap();
+        assert_eq!((cms.config().width, cms.config().depth), (1 << 10, 4));
+        cms.add("x", 3);
+        cms.add("x", 3);
+        assert_eq!(cms.estimate("x"), 5);
+
+        let builder = CountMinSketch32::<&str>::builder;
+        assert!(matches!(
+            builder().depth(4).build(),
+            Err(CmsError::Builder(_))
+        ));
+        assert!(matches!(
+            builder()
+                .width(1024)
+                .capacity(100)
+                .tolerance(1.0)
+                .depth(4)
+                .build(),
+            Err(CmsError::Builder(_))
+        ));
+        assert!(matches!(
+            builder().width(1000).depth(4).build(),
+            Err(CmsError::Width(1000))
+        ));
+        assert!(matches!(
+            builder().width(1024).probability(2.0).build(),
+            Err(CmsError::Probability(_))
+        ));
+    }
+
+    #[test]
+    fn test_with_dimensions() {
+        use crate::{CmsError, CountMinSketch16};
+
+        let mut cms = CountMinSketch16::<&str>::with_dimensions(1 << 20, 4).unwrap();
+        assert_eq!((cms.config().width, cms.config().depth), (1 << 20, 4));
+        cms.add("x", 3);
+        assert_eq!(cms.estimate("x"), 3);
+        let cms = CountMinSketch16::<&str>::with_dimensions(1000, 2).unwrap();
+        assert_eq!(cms.config().width, 1024);
+        assert_eq!(
+            CountMinSketch16::<&str>::with_dimensions(1024, 0).err(),
+            Some(CmsError::Depth(0))
+        );
+    }
+
+    #[test]
+    fn test_dimension_getters() {
+        use crate::CountMinSketch32;
+
+        let cms = CountMinSketch32::<&str>::new(1000, 0.99, 10.0).unwrap();
+        assert_eq!(cms.width() as u64, cms.config().width);
+        assert_eq!(cms.depth() as u64, cms.config().depth);
+        assert!(cms.epsilon() <= 10.0 / 1000.0);
+        assert_eq!(cms.failure_probability(), 0.5f64.powi(cms.depth() as i32));
+        let cms = CountMinSketch32::<&str>::with_dimensions(1024, 3).unwrap();
+        assert_eq!(cms.epsilon(), 2.0 / 1024.0);
+        assert_eq!(cms.failure_probability(), 0.125);
+    }
+
+    #[test]
+    fn test_estimate_with_bound() {
+        use crate::CountMinSketch32;
+
+        let builder = CountMinSketch32::<u32>::builder().width(64).depth(4);
+        let mut cms = builder.seeds([1, 2, 3, 4]).build().unwrap();
+        assert_eq!(cms.estimate_with_bound(&1), (0, 0));
+        for i in 0..1000 {
+            cms.increment(&(i % 100));
+        }
+        let (estimate, bound) = cms.estimate_with_bound(&7);
+        assert_eq!(bound, 2 * 1000 / 64 + 1);
+        assert!(estimate >= 10 && estimate as u64 <= 10 + bound);
+    }
+
+    #[test]
+    fn test_standard_update() {
+        use crate::CountMinSketch32;
+
+        let seeds = [5, 6, 7, 8];
+        let mut a = CountMinSketch32::<u32>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut b = CountMinSketch32::<u32>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut both = CountMinSketch32::<u32>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        for cms in [&mut a, &mut b, &mut both] {
+            assert!(cms.conservative_update());
+            cms.set_conservative_update(false);
+        }
+        for i in 0..500 {
+            a.increment(&(i % 50));
+            b.add(&(i % 70), 2);
+            both.increment(&(i % 50));
+            both.add(&(i % 70), 2);
+        }
+        a.merge(&b).unwrap();
+        assert_eq!(a.digest(), both.digest());
+        assert_eq!(a.total(), 1500);
+    }
+
+    #[test]
+    fn test_remove() {
+        use crate::{CmsError, CountMinSketch16};
+
+        let mut cms = CountMinSketch16::<&str>::new(100, 0.95, 10.0).unwrap();
+        cms.add("10.0.0.1", 3);
+        assert_eq!(cms.decrement("10.0.0.1"), Err(CmsError::ConservativeUpdate));
+        cms.set_conservative_update(false);
+        cms.decrement("10.0.0.1").unwrap();
+        assert_eq!(cms.estimate("10.0.0.1"), 2);
+        cms.remove("10.0.0.1", 5).unwrap();
+        assert_eq!(cms.estimate("10.0.0.1"), 0);
+        assert_eq!(cms.total(), 0);
+    }
+
+    #[test]
+    fn test_count_sketch() {
+        use crate::CountSketch;
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountSketch::<u32>::new_with_keys(10_000, 0.97, 10.0, seeds).unwrap();
+        let mut b = CountSketch::<u32>::new_with_keys(10_000, 0.97, 10.0, seeds).unwrap();
+        for i in 0..10_000u32 {
+            a.increment(&(i % 100));
+        }
+        b.add(&7, -50);
+        a.merge(&b).unwrap();
+        assert!((a.estimate(&7) - 50).abs() <= 20);
+        assert!(a.estimate(&1_000_000).abs() <= 20);
+        a.clear();
+        assert_eq!(a.estimate(&7), 0);
+        let c = CountSketch::<u32>::new(10_000, 0.97, 10.0).unwrap();
+        assert!(a.merge(&c).is_err());
+    }
+
+    #[test]
+    fn test_heavy_hitters() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch32::<String>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        assert_eq!(cms.heavy_hitters().count(), 0);
+        cms.enable_heavy_hitters(100);
+        for i in 0..1000 {
+            cms.increment_tracked(&(i % 8).to_string());
+            cms.increment_tracked(&i.to_string());
+        }
+        let heavy: Vec<_> = cms.heavy_hitters().collect();
+        assert_eq!(heavy.len(), 8);
+        assert!(heavy.iter().all(|&(_, estimate)| estimate >= 125));
+        cms.clear_keep_seeds();
+        assert_eq!(cms.heavy_hitters().count(), 0);
+    }
+
+    #[test]
+    fn test_top_k() {
+        use crate::TopK;
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = TopK::<u32>::new_with_keys(1000, 0.95, 10.0, 3, seeds).unwrap();
+        let mut b = TopK::<u32>::new_with_keys(1000, 0.95, 10.0, 3, seeds).unwrap();
+        for i in 0..1000u32 {
+            a.insert(&(i % 10));
+            a.add(&1, 1);
+            a.add(&2, 2);
+            b.insert(&(i % 7));
+        }
+        let top: Vec<_> = a.top(3).into_iter().map(|(&key, _)| key).collect();
+        assert_eq!(top[..2], [2, 1]);
+        assert_eq!(a.top(1)[0], (&2, 2100));
+        b.add(&5, 5000);
+        a.merge(&b).unwrap();
+        assert_eq!(a.top(1)[0].0, &5);
+        assert_eq!(a.top(10).len(), 3);
+        let c = TopK::<u32>::new(1000, 0.95, 10.0, 3).unwrap();
+        assert!(a.merge(&c).is_err());
+    }
+
+    #[test]
+    fn test_sliding_sketch() {
+        use crate::{CmsError, SlidingCountMinSketch};
+
+        let mut cms = SlidingCountMinSketch::<&str>::new(100, 0.95, 10.0, 3).unwrap();
+        cms.add("x", 5);
+        cms.tick();
+        cms.add("x", 2);
+        cms.tick();
+        assert_eq!(cms.estimate("x"), 7);
+        assert_eq!(cms.total(), 7);
+        cms.tick();
+        assert_eq!(cms.estimate("x"), 2);
+        cms.advance(10);
+        assert_eq!(cms.estimate("x"), 0);
+        assert_eq!(
+            SlidingCountMinSketch::<&str>::new(100, 0.95, 10.0, 0).err(),
+            Some(CmsError::Slots(0))
+        );
+    }
+
+    #[test]
+    fn test_exp_decay_sketch() {
+        use crate::{CmsError, ExpDecaySketch};
+        use std::time::Duration;
+
+        let lambda = 2f64.ln() / 60.0;
+        let mut cms = ExpDecaySketch::<&str>::new(10_000, 0.95, 10.0, lambda).unwrap();
+        let minute = Duration::from_secs(60);
+        cms.add("x", 8.0, Duration::ZERO);
+        assert!((cms.estimate("x", minute) - 4.0).abs() < 1e-9);
+        cms.increment("x", minute);
+        assert!((cms.estimate("x", minute * 2) - 2.5).abs() < 1e-9);
+        assert_eq!(cms.estimate("y", minute), 0.0);
+        assert_eq!(
+            ExpDecaySketch::<&str>::new(100, 0.95, 10.0, -1.0).err(),
+            Some(CmsError::DecayRate(-1.0))
+        );
+    }
+
+    #[test]
+    fn test_decay() {
+        use crate::{CmsError, CountMinSketch32, Rounding};
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.add("x", 100);
+        cms.add("y", 3);
+        cms.decay(0.9).unwrap();
+        assert_eq!((cms.estimate("x"), cms.estimate("y")), (90, 2));
+        assert_eq!(cms.total(), 92);
+        cms.set_rounding(Rounding::HalfUp);
+        cms.decay(0.5).unwrap();
+        assert_eq!((cms.estimate("x"), cms.estimate("y")), (45, 1));
+        cms.set_rounding(Rounding::KeepNonZero);
+        cms.decay(0.1).unwrap();
+        assert_eq!((cms.estimate("x"), cms.estimate("y")), (4, 1));
+        assert_eq!(cms.decay(1.5), Err(CmsError::DecayFactor(1.5)));
+        assert!(cms.decay(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_reset_next_n() {
+        use crate::CountMinSketch32;
+
+        let mut cms = CountMinSketch32::<&str>::with_dimensions(1024, 3).unwrap();
+        cms.add("x", 8);
+        assert_eq!(cms.reset_next_n(0), Some(0));
+        assert_eq!(cms.reset_next_n(1000), Some(1000));
+        assert_eq!(cms.reset_next_n(1000), None);
+        assert_eq!(cms.estimate("x"), 4);
+        assert_eq!(cms.total(), 4);
+        assert_eq!(cms.reset_next_n(10), Some(10));
+    }
+
+    #[test]
+    fn test_decay_policy() {
+        use crate::{CountMinSketch32, DecayPolicy, ManualClock};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let mut cms = CountMinSketch32::<&str>::builder()
+            .capacity(100)
+            .probability(0.95)
+            .tolerance(10.0)
+            .decay_policy(DecayPolicy::EveryNInserts(4))
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            cms.add("x", 4);
+        }
+        assert_eq!(cms.estimate("x"), 12);
+        cms.add("x", 4);
+        assert_eq!(cms.estimate("x"), 8);
+
+        let minute = Duration::from_secs(60);
+        cms.set_decay_policy(DecayPolicy::EveryDuration(minute), Duration::ZERO);
+        cms.add_at("x", 8, minute / 2);
+        assert_eq!(cms.estimate("x"), 16);
+        cms.add_at("x", 0, minute * 2);
+        assert_eq!(cms.estimate("x"), 4);
+        cms.add("x", 1);
+        assert_eq!(cms.estimate("x"), 5);
+
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let builder = || {
+            CountMinSketch32::<&str>::builder()
+                .capacity(100)
+                .probability(0.95)
+                .tolerance(10.0)
+                .decay_policy(DecayPolicy::EveryDuration(minute))
+        };
+        assert!(builder().build().is_err());
+        let mut cms = builder().clock(Arc::clone(&clock)).build().unwrap();
+        cms.add("y", 8);
+        clock.advance(minute);
+        cms.increment("y");
+        assert_eq!(cms.estimate("y"), 5);
+    }
+
+    #[test]
+    fn test_windowed_count_min() {
+        use crate::WindowedCountMin;
+
+        let mut filter = WindowedCountMin::<&str>::new_with_keys(100, 10, [1, 2, 3, 4]).unwrap();
+        for _ in 0..5 {
+            filter.record_access("hot");
+        }
+        filter.record_access("cold");
+        assert_eq!(filter.frequency("hot"), 5);
+        for _ in 0..4 {
+            filter.record_access("hot");
+        }
+        assert_eq!(filter.frequency("hot"), 9);
+        assert!(filter.admit("hot", "cold"));
+        for _ in 0..1000 {
+            filter.record_access("other");
+        }
+        assert!(filter.frequency("hot") < 9);
+        filter.clear();
+        assert_eq!(filter.frequency("hot"), 0);
+    }
+
+    #[test]
+    fn test_compare() {
+        use crate::CountMinSketch8;
+        use std::cmp::Ordering;
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch8::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.add("candidate", 5);
+        cms.add("victim", 3);
+        assert_eq!(cms.compare("candidate", "victim"), Ordering::Greater);
+        assert_eq!(cms.compare("victim", "candidate"), Ordering::Less);
+        assert_eq!(cms.compare("absent", "absent"), Ordering::Equal);
+        assert!(cms.frequency_ge("candidate", "victim"));
+        assert!(cms.frequency_ge("victim", "victim"));
+        assert!(!cms.frequency_ge("victim", "candidate"));
+    }
+
+    #[test]
+    fn test_add_all() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountMinSketch32::<u32>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        let mut b = CountMinSketch32::<u32>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        a.add_all((0..1000u32).map(|i| (i % 37, i % 5)));
+        a.increment_all(0..100);
+        for i in 0..1000u32 {
+            b.add(&(i % 37), i % 5);
+        }
+        for i in 0..100 {
+            b.increment(&i);
+        }
+        assert_eq!(a.digest(), b.digest());
+        assert_eq!(a.total(), b.total());
+    }
+
+    #[test]
+    fn test_estimate_many() {
+        use crate::CountMinSketch16;
+
+        let mut cms = CountMinSketch16::<u32>::new(1000, 0.95, 10.0).unwrap();
+        for i in 0..1000u32 {
+            cms.increment(&(i % 50));
+        }
+        let keys: Vec<u32> = (0..100).collect();
+        let expected: Vec<u16> = keys.iter().map(|key| cms.estimate(key)).collect();
+        assert_eq!(cms.estimate_many(&keys), expected);
+        assert!(cms.estimate_many::<u32>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_hashed_key() {
+        use crate::{CountMinSketch32, CountMinSketch8};
+
+        let seeds = [1, 2, 3, 4];
+        let mut a = CountMinSketch32::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        let mut b = CountMinSketch8::<&str>::new_with_keys(1000, 0.5, 1.0, seeds).unwrap();
+        let key = a.hash_key("x");
+        a.add_hashed(&key, 3);
+        b.increment_hashed(&key);
+        assert_eq!(a.estimate("x"), 3);
+        assert_eq!(a.estimate_hashed(&key), 3);
+        assert_eq!(b.estimate("x"), 1);
+        assert_eq!(b.hash_key("x"), key);
+    }
+
+    #[test]
+    fn test_add_and_estimate() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        for &conservative in &[false, true] {
+            let mut cms = CountMinSketch32::<u32>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+            cms.set_conservative_update(conservative);
+            for i in 0..1000u32 {
+                let estimate = cms.add_and_estimate(&(i % 300), i % 4);
+                assert_eq!(estimate, cms.estimate(&(i % 300)));
+            }
+            assert_eq!(cms.increment_and_estimate(&7), cms.estimate(&7));
+        }
+    }
+
+    #[test]
+    fn test_slice_ops() {
+        use crate::simd::{add_scalar, halve_scalar};
+        use crate::{Counter, Rounding};
+        use rand::Rng;
+
+        let mut rng = rand::thread_rng();
+        for len in 0..70 {
+            let a8: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            let b8: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            let a16: Vec<u16> = (0..len).map(|_| rng.gen_range(0..4) * 0x5555).collect();
+            let b16: Vec<u16> = (0..len).map(|_| rng.gen()).collect();
+            for &rounding in &[Rounding::Down, Rounding::HalfUp, Rounding::KeepNonZero] {
+                let (mut fast, mut slow) = (a8.clone(), a8.clone());
+                u8::halve_slice(&mut fast, rounding);
+                halve_scalar(&mut slow, rounding);
+                assert_eq!(fast, slow);
+                let (mut fast, mut slow) = (a16.clone(), a16.clone());
+                u16::halve_slice(&mut fast, rounding);
+                halve_scalar(&mut slow, rounding);
+                assert_eq!(fast, slow);
+            }
+            for &ceiling in &[15, 200, u8::MAX] {
+                let (mut fast, mut slow) = (a8.clone(), a8.clone());
+                u8::add_slice(&mut fast, &b8, ceiling);
+                add_scalar(&mut slow, &b8, ceiling);
+                assert_eq!(fast, slow);
+            }
+            for &ceiling in &[15, 40_000, u16::MAX] {
+                let (mut fast, mut slow) = (a16.clone(), a16.clone());
+                u16::add_slice(&mut fast, &b16, ceiling);
+                add_scalar(&mut slow, &b16, ceiling);
+                assert_eq!(fast, slow);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blocked_sketch() {
+        use crate::{BlockedCountMinSketch, CmsError, CountMinSketch16, Incompatibility};
+
+        let seeds = [1, 2, 3, 4];
+        let mut a =
+            BlockedCountMinSketch::<u32, u16>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        let mut b =
+            BlockedCountMinSketch::<u32, u16>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        let cms = CountMinSketch16::<u32>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        assert_eq!((a.width(), a.depth()), (cms.width(), cms.depth()));
+        for i in 0..1000u32 {
+            a.add(&(i % 10), 2);
+            b.increment(&(i % 10));
+        }
+        for key in 0..10 {
+            assert!(a.estimate(&key) >= 200);
+            assert!(b.estimate(&key) >= 100);
+        }
+        a.merge(&b).unwrap();
+        for key in 0..10 {
+            assert!(a.estimate(&key) >= 300);
+        }
+        a.clear();
+        assert_eq!(a.estimate(&0), 0);
+
+        let c = BlockedCountMinSketch::<u32, u16>::new(1000, 0.95, 10.0).unwrap();
+        assert!(matches!(a.merge(&c), Err(Incompatibility::Seeds)));
+        assert!(matches!(
+            BlockedCountMinSketch::<u32, u64>::new(1000, 0.999, 10.0),
+            Err(CmsError::Depth(9))
+        ));
+    }
+
+    #[test]
+    fn test_sparse_bytes() {
+        use crate::{CountMinSketch16, CountMinSketch64};
+
+        let mut cms = CountMinSketch16::<u32>::new(10_000, 0.95, 10.0).unwrap();
+        let empty = cms.to_sparse_bytes();
+        assert!(empty.len() < 110);
+        assert_eq!(
+            CountMinSketch16::<u32>::from_bytes(&empty)
+                .unwrap()
+                .digest(),
+            cms.digest()
+        );
+        for key in 0..100u32 {
+            cms.add(&key, key as u16 * 300);
+        }
+        let sparse = cms.to_sparse_bytes();
+        assert!(sparse.len() < cms.to_bytes().len() / 10);
+        let restored = CountMinSketch16::<u32>::from_bytes(&sparse).unwrap();
+        assert_eq!(restored.digest(), cms.digest());
+        assert_eq!(restored.total(), cms.total());
+        assert!(CountMinSketch16::<u32>::from_bytes(&sparse[..sparse.len() - 1]).is_err());
+        assert!(CountMinSketch64::<u32>::from_bytes(&sparse).is_err());
+
+        let mut full = CountMinSketch16::<u32>::new(100, 0.95, 10.0).unwrap();
+        full.map_counters(|_| 7);
+        let restored = CountMinSketch16::<u32>::from_bytes(&full.to_sparse_bytes()).unwrap();
+        assert_eq!(restored.digest(), full.digest());
+    }
+
+    #[test]
+    fn test_wire_format() {
+        use crate::{
+            CountMinSketch16, DecayPolicy, OverflowPolicy, Rounding, SketchConfig, Storage,
+        };
+
+        let config = SketchConfig {
+            width: 4,
+            depth: 2,
+            seeds: [1, 2, 3, 4],
+            counter_size: 2,
+            ceiling: 1000,
+            conservative: true,
+            rounding: Rounding::Down,
+            overflow_policy: OverflowPolicy::Saturate,
+            decay_policy: DecayPolicy::Never,
+        };
+        let mut bytes = b"CMSK\x02CMSC\x02\x02".to_vec();
+        for field in &[4u64, 2, 1, 2, 3, 4, 1000] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[1, 0, 0, 0]);
+        for field in &[0u64, 42, 3, 5_000_000_000] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        for counter in &[0u16, 0, 0, 0, 0, 0, 7, 0] {
+            bytes.extend_from_slice(&counter.to_le_bytes());
+        }
+        let cms = CountMinSketch16::<u32>::from_bytes(&bytes).unwrap();
+        assert_eq!(cms.config(), config);
+        assert_eq!(cms.total(), 42);
+        assert_eq!(cms.reset_idx, 3);
+        assert_eq!(cms.updated_at.as_secs(), 5);
+        assert_eq!(cms.counters.row(1)[2], 7);
+        assert_eq!(cms.to_bytes(), bytes);
+
+        bytes[4] = 1;
+        assert_eq!(
+            CountMinSketch16::<u32>::from_bytes(&bytes).err(),
+            Some("Unsupported sketch version")
+        );
+    }
+
+    #[test]
+    fn test_write_bytes() {
+        use crate::{CmsError, CountMinSketch32};
+
+        let mut cms = CountMinSketch32::<u32>::new(100, 0.95, 10.0).unwrap();
+        for key in 0..100u32 {
+            cms.add(&key, key);
+        }
+        let len = cms.serialized_len();
+        let mut out = vec![0xffu8; len + 3];
+        assert_eq!(cms.write_bytes(&mut out), Ok(len));
+        assert_eq!(&out[..len], &cms.to_bytes()[..]);
+        assert_eq!(&out[len..], &[0xff; 3]);
+        assert_eq!(
+            cms.write_bytes(&mut out[..len - 1]),
+            Err(CmsError::BufferTooSmall { needed: len })
+        );
+    }
+
+    #[test]
+    fn test_shared_sketch() {
+        use crate::{CmsError, SharedCountMinSketch};
+        use std::sync::atomic::AtomicU64;
+
+        let len = SharedCountMinSketch::<u32>::region_len(1000, 0.95, 10.0).unwrap();
+        let region: Vec<AtomicU64> = (0..len + 1).map(|_| AtomicU64::new(u64::MAX)).collect();
+        assert!(SharedCountMinSketch::<u32>::attach(&region).is_err());
+        assert!(matches!(
+            SharedCountMinSketch::<u32>::init(&region[..len - 1], 1000, 0.95, 10.0),
+            Err(CmsError::BufferTooSmall { needed }) if needed == len
+        ));
+        let owner = SharedCountMinSketch::<u32>::init(&region, 1000, 0.95, 10.0).unwrap();
+        let worker = SharedCountMinSketch::<u32>::attach(&region).unwrap();
+        assert_eq!(owner.config(), worker.config());
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let worker = SharedCountMinSketch::<u32>::attach(&region).unwrap();
+                    for _ in 0..1000 {
+                        worker.increment(&7);
+                    }
+                });
+            }
+        });
+        assert!(owner.estimate(&7) >= 4000);
+        assert_eq!(worker.total(), 4000);
+        let snapshot = worker.snapshot();
+        assert_eq!(snapshot.estimate(&7), owner.estimate(&7));
+        owner.clear();
+        assert_eq!((worker.estimate(&7), worker.total()), (0, 0));
+        assert!(SharedCountMinSketch::<u32>::attach(&region[..len - 1]).is_err());
+    }
+
+    #[test]
+    fn test_from_storage() {
+        use crate::{
+            CmsError, CountMinSketch, DecayPolicy, OverflowPolicy, Rounding, SketchConfig,
+            SliceStorage,
+        };
+
+        let config = SketchConfig {
+            width: 256,
+            depth: 4,
+            seeds: [1, 2, 3, 4],
+            counter_size: 4,
+            ceiling: 1000,
+            conservative: true,
+            rounding: Rounding::Down,
+            overflow_policy: OverflowPolicy::Saturate,
+            decay_policy: DecayPolicy::Never,
+        };
+        // Stands for a file-backed mapping
+        let mut file = vec![0u32; 256 * 4 + 10];
+        {
+            let storage = SliceStorage::new(&mut file, 256).unwrap();
+            let mut cms = CountMinSketch::<&str, u32, _>::from_storage(storage, &config).unwrap();
+            cms.add("persisted", 5000);
+            assert_eq!(cms.estimate("persisted"), 1000);
+        }
+        let storage = SliceStorage::new(&mut file, 256).unwrap();
+        let cms = CountMinSketch::<&str, u32, _>::from_storage(storage, &config).unwrap();
+        assert_eq!(cms.estimate("persisted"), 1000);
+        assert_eq!(cms.config(), config);
+
+        let storage = SliceStorage::new(&mut file[..256 * 3], 256).unwrap();
+        assert_eq!(
+            CountMinSketch::<&str, u32, _>::from_storage(storage, &config).err(),
+            Some(CmsError::BufferTooSmall { needed: 1024 })
+        );
+        let storage = SliceStorage::new(&mut file, 128).unwrap();
+        assert_eq!(
+            CountMinSketch::<&str, u32, _>::from_storage(storage, &config).err(),
+            Some(CmsError::Width(128))
+        );
+        assert!(SliceStorage::new(&mut file, 0).is_err());
+
+        let mut rows = vec![vec![0u32; 256]; 4];
+        rows[3].truncate(100);
+        assert_eq!(
+            CountMinSketch::<&str, u32, _>::from_storage(rows, &config).err(),
+            Some(CmsError::Width(100))
+        );
+    }
+
+    #[test]
+    fn test_widen() {
+        use crate::{CountMinSketch16, CountMinSketch64, CountMinSketch8};
+
+        let seeds = [1, 2, 3, 4];
+        let mut narrow = CountMinSketch8::<u32>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        for key in 0..100u32 {
+            narrow.add(&key, key as u8);
+        }
+        narrow.add(&7, u8::MAX);
+        let config = narrow.config();
+        let estimates: Vec<u8> = (0..100).map(|key| narrow.estimate(&key)).collect();
+        let mut wide = CountMinSketch16::from(narrow);
+        assert_eq!(wide.config().seeds, config.seeds);
+        assert_eq!(wide.config().ceiling, u16::MAX as u64);
+        for key in 0..100u32 {
+            assert_eq!(wide.estimate(&key), estimates[key as usize] as u16);
+        }
+        wide.add(&7, 1000);
+        assert_eq!(wide.estimate(&7), 1255);
+
+        let mut capped = CountMinSketch16::<u32>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        capped.set_ceiling(500);
+        capped.add(&1, 600);
+        let capped: CountMinSketch64<u32> = capped.into();
+        assert_eq!(capped.config().ceiling, 500);
+        assert_eq!(capped.estimate(&1), 500);
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        use crate::{
+            CountMinSketch, CountMinSketch8, DecayPolicy, OverflowPolicy, SaturationError,
+        };
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::time::Duration;
+
+        static REPORTED: AtomicU64 = AtomicU64::new(0);
+        fn report(err: SaturationError) {
+            assert_eq!(err.ceiling, 255);
+            REPORTED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch::<&str, u8>::builder()
+            .capacity(100)
+            .probability(0.95)
+            .tolerance(10.0)
+            .seeds(seeds)
+            .overflow_policy(OverflowPolicy::Error)
+            .overflow_handler(report)
+            .build()
+            .unwrap();
+        assert_eq!(cms.overflow_policy(), OverflowPolicy::Error);
+        cms.add("a", 200);
+        cms.add("a", 100);
+        assert_eq!((cms.estimate("a"), cms.total()), (200, 200));
+        assert_eq!(cms.rejected_updates(), 1);
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 1);
+        cms.add("a", 55);
+        assert_eq!((cms.estimate("a"), cms.rejected_updates()), (255, 1));
+        cms.set_overflow_handler(None);
+        cms.set_conservative_update(false);
+        cms.set_ceiling(100);
+        cms.add("b", 100);
+        cms.add("b", 1);
+        assert_eq!((cms.estimate("b"), cms.rejected_updates()), (100, 2));
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 1);
+        cms.clear_keep_seeds();
+        assert_eq!(cms.rejected_updates(), 0);
+
+        // Rejected updates don't age the sketch
+        cms.set_ceiling(255);
+        cms.set_decay_policy(DecayPolicy::EveryNInserts(2), Duration::ZERO);
+        cms.add("c", 250);
+        cms.add("c", 10);
+        assert_eq!(cms.estimate("c"), 250);
+
+        let mut cms = CountMinSketch8::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.set_overflow_policy(OverflowPolicy::Wrap);
+        cms.add("a", 200);
+        assert_eq!(cms.add_and_estimate("a", 100), 44);
+        assert_eq!(cms.total(), 300);
+    }
+
+    #[test]
+    fn test_try_add() {
+        use crate::{CountMinSketch16, OverflowPolicy, SaturationError};
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch16::<&str>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.set_ceiling(1000);
+        assert_eq!(cms.try_add("a", 600), Ok(600));
+        assert_eq!(
+            cms.try_add("a", 401),
+            Err(SaturationError { ceiling: 1000 })
+        );
+        assert_eq!((cms.estimate("a"), cms.total()), (600, 600));
+        assert_eq!(cms.try_add("a", 400), Ok(1000));
+        cms.set_overflow_policy(OverflowPolicy::Wrap);
+        assert!(cms.try_add("a", 1).is_err());
+        assert_eq!(cms.estimate("a"), 1000);
+    }
+
+    #[test]
+    fn test_saturation_metrics() {
+        use crate::CountMinSketch8;
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch8::<u32>::with_dimensions(16, 2).unwrap();
+        assert_eq!(cms.saturated_ratio(), 0.0);
+        cms.add(&1, 200);
+        cms.add(&1, 55);
+        assert_eq!(cms.saturated_updates(), 0);
+        assert_eq!(cms.saturated_ratio(), 2.0 / 32.0);
+        cms.add(&1, 1);
+        assert_eq!(cms.saturated_updates(), 1);
+        cms.set_conservative_update(false);
+        cms.add(&1, 1);
+        assert_eq!(cms.saturated_updates(), 2);
+        cms.clear_keep_seeds();
+        assert_eq!((cms.saturated_updates(), cms.saturated_ratio()), (0, 0.0));
+
+        let mut cms = CountMinSketch8::<u32>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        cms.set_ceiling(10);
+        cms.add(&1, 20);
+        assert_eq!(cms.saturated_updates(), 1);
+        assert!(cms.saturated_ratio() > 0.0);
+    }
+
+    #[test]
+    fn test_stats() {
+        use crate::CountMinSketch16;
+
+        let mut cms = CountMinSketch16::<u32>::with_dimensions(64, 4).unwrap();
+        cms.set_conservative_update(false);
+        let stats = cms.stats();
+        assert_eq!((stats.width, stats.depth, stats.total), (64, 4, 0));
+        assert_eq!((stats.occupancy, stats.max, stats.mean), (0.0, 0, 0.0));
+        cms.add(&1, 10);
+        cms.add(&2, 6);
+        let stats = cms.stats();
+        assert_eq!(stats.total, 16);
+        assert!(stats.occupancy > 0.0 && stats.occupancy <= 8.0 / 256.0);
+        assert!(stats.max >= 10);
+        assert_eq!(stats.mean, 64.0 / 256.0);
+        assert_eq!(stats.error_bound, cms.estimate_with_bound(&1).1);
+    }
+
+    #[test]
+    fn test_prune() {
+        use crate::CountMinSketch16;
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch16::<u32>::new_with_keys(1000, 0.95, 10.0, seeds).unwrap();
+        for key in 0..200 {
+            cms.increment(&key);
+        }
+        cms.add(&1000, 50);
+        cms.pin(&7);
+        let sparse_len = cms.to_sparse_bytes().len();
+        cms.prune(10);
+        assert!(cms.to_sparse_bytes().len() < sparse_len);
+        assert_eq!(cms.estimate(&1000), 50);
+        assert_eq!(cms.estimate(&7), 1);
+        assert_eq!(cms.estimate(&8), 0);
+        assert_eq!(cms.total(), 250);
+    }
+
+    #[test]
+    fn test_export_matrix() {
+        use crate::CountMinSketch8;
+
+        let mut cms = CountMinSketch8::<u32>::builder()
+            .width(4)
+            .depth(2)
+            .seeds([1, 2, 3, 4])
+            .build()
+            .unwrap();
+        cms.add(&1, 3);
+        let matrix = cms.export_matrix();
+        assert_eq!(
+            (matrix.width, matrix.depth, matrix.seeds),
+            (4, 2, [1, 2, 3, 4])
+        );
+        assert_eq!(matrix.rows.len(), 2);
+        for row in &matrix.rows {
+            assert_eq!(row.len(), 4);
+            assert_eq!(row.iter().sum::<u64>(), 3);
+        }
+
+        #[cfg(feature = "export")]
+        {
+            let mut csv = Vec::new();
+            matrix.write_csv(&mut csv).unwrap();
+            let csv = String::from_utf8(csv).unwrap();
+            let mut lines = csv.lines();
+            assert_eq!(lines.next(), Some("# width=4 depth=2 seeds=1,2,3,4"));
+            assert_eq!(lines.count(), 2);
+
+            let mut json = Vec::new();
+            matrix.write_json(&mut json).unwrap();
+            let json = String::from_utf8(json).unwrap();
+            assert!(json.starts_with("{\"width\":4,\"depth\":2,\"seeds\":[1,2,3,4],\"rows\":[["));
+            assert!(json.ends_with("]]}\n"));
+        }
+    }
+
+    #[test]
+    fn test_raw_parts() {
+        use crate::{CmsError, CountMinSketch32};
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = CountMinSketch32::<u32>::new_with_keys(100, 0.95, 10.0, seeds).unwrap();
+        for key in 0..50 {
+            cms.add(&key, key);
+        }
+        let rows: Vec<Vec<u32>> = cms.rows().map(|row| row.to_vec()).collect();
+        assert_eq!(rows.len(), cms.depth());
+        let (width, depth) = (cms.width(), cms.depth());
+        let restored = CountMinSketch32::<u32>::from_raw_parts(&rows, seeds, width, depth).unwrap();
+        for key in 0..50 {
+            assert_eq!(restored.estimate(&key), cms.estimate(&key));
+        }
+        assert!(restored.is_mergeable(&cms).is_ok());
+
+        let short = CountMinSketch32::<u32>::from_raw_parts(&rows[1..], seeds, width, depth);
+        assert_eq!(short.err(), Some(CmsError::Depth(depth as u64 - 1)));
+        let narrow = CountMinSketch32::<u32>::from_raw_parts([[0u32; 2]], seeds, width, 1);
+        assert_eq!(narrow.err(), Some(CmsError::Width(2)));
+    }
+
+    #[test]
+    fn test_inner_product() {
+        use crate::{CountMinSketch32, Incompatibility};
+
+        let seeds = [1, 2, 3, 4];
+        let new = || {
+            let mut cms = CountMinSketch32::<u32>::new_with_keys(1000, 0.99, 10.0, seeds).unwrap();
+            cms.set_conservative_update(false);
+            cms
+        };
+        let (mut a, mut b) = (new(), new());
+        for key in 0..100 {
+            a.add(&key, 2);
+        }
+        for key in 50..150 {
+            b.add(&key, 3);
+        }
+        let product = a.inner_product(&b).unwrap();
+        let bound = (a.epsilon() * a.total() as f64 * b.total() as f64) as u128;
+        assert!(product >= 50 * 6 && product <= 50 * 6 + bound);
+        assert_eq!(a.inner_product(&new()), Ok(0));
+
+        let other = CountMinSketch32::<u32>::new_with_keys(1000, 0.99, 10.0, [5, 6, 7, 8]).unwrap();
+        assert_eq!(a.inner_product(&other), Err(Incompatibility::Seeds));
+    }
+
+    #[test]
+    fn test_similarity() {
+        use crate::CountMinSketch32;
+
+        let seeds = [1, 2, 3, 4];
+        let new = || CountMinSketch32::<u32>::new_with_keys(1000, 0.99, 1.0, seeds).unwrap();
+        let (mut a, mut b, mut c) = (new(), new(), new());
+        for key in 0..100 {
+            a.add(&key, key + 1);
+            b.add(&key, 2 * (key + 1));
+            c.add(&(key + 1000), key + 1);
+        }
+        assert!((a.similarity(&b).unwrap() - 1.0).abs() < 1e-9);
+        assert!(a.similarity(&c).unwrap() < 0.1);
+        assert_eq!(a.similarity(&new()), Ok(0.0));
+    }
+
+    #[test]
+    fn test_union_max() {
+        use crate::{CountMinSketch16, Incompatibility};
+
+        let seeds = [1, 2, 3, 4];
+        let new = || CountMinSketch16::<u32>::new_with_keys(1000, 0.99, 10.0, seeds).unwrap();
+        let (mut a, mut b) = (new(), new());
+        a.add(&1, 10);
+        a.add(&2, 5);
+        b.add(&1, 7);
+        b.add(&3, 4);
+        a.union_max(&b).unwrap();
+        assert_eq!(a.estimate(&1), 10);
+        assert_eq!(a.estimate(&2), 5);
+        assert_eq!(a.estimate(&3), 4);
+        assert_eq!(a.total(), 15);
+
+        let other = CountMinSketch16::<u32>::new_with_keys(1000, 0.99, 1.0, seeds).unwrap();
+        assert!(matches!(
+            a.union_max(&other),
+            Err(Incompatibility::Width { .. })
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_delta() {
+        use crate::{CountMinSketch32, DELTA_HEADER_LEN};
+        use std::time::Duration;
+
+        let seeds = [1, 2, 3, 4];
+        let new = || CountMinSketch32::<u32>::new_with_keys(1000, 0.99, 10.0, seeds).unwrap();
+        let (mut source, mut replica) = (new(), new());
+        for key in 0..100 {
+            source.add(&key, key);
+        }
+        let snapshot = new().snapshot();
+        let first = source.delta_since(&snapshot).unwrap();
+        replica.apply_delta(&first).unwrap();
+        assert_eq!(replica.digest(), source.digest());
+        assert_eq!(replica.total(), source.total());
+
+        let snapshot = source.snapshot();
+        source.add_at(&7, 3, Duration::from_secs(5));
+        let second = source.delta_since(&snapshot).unwrap();
+        assert!(second.len() < first.len());
+        assert_eq!(
+            replica.apply_delta(&second[..second.len() - 1]),
+            Err("Delta has an unexpected length")
+        );
+        replica.apply_delta(&second).unwrap();
+        assert_eq!(replica.digest(), source.digest());
+        assert_eq!(replica.total(), source.total());
+        assert_eq!(replica.updated_at(), Duration::from_secs(5));
+
+        let snapshot = source.snapshot();
+        source.reset();
+        replica
+            .apply_delta(&source.delta_since(&snapshot).unwrap())
+            .unwrap();
+        assert_eq!(replica.digest(), source.digest());
+        assert_eq!(
+            replica.apply_delta(&second),
+            Err("Delta doesn't apply to the current counters")
+        );
+
+        let unchanged = source.delta_since(&source.snapshot()).unwrap();
+        assert_eq!(unchanged.len(), DELTA_HEADER_LEN);
+    }
+
+    #[test]
+    fn test_merge_scaled() {
+        use crate::{CountMinSketch32, Rounding};
+
+        let seeds = [1, 2, 3, 4];
+        let new = || CountMinSketch32::<u32>::new_with_keys(1000, 0.99, 10.0, seeds).unwrap();
+        let (mut daily, mut hourly) = (new(), new());
+        daily.add(&1, 100);
+        hourly.add(&1, 50);
+        hourly.add(&2, 3);
+        daily.merge_scaled(&hourly, 0.5).unwrap();
+        assert_eq!(daily.estimate(&1), 125);
+        assert_eq!(daily.estimate(&2), 1);
+        assert_eq!(daily.total(), 126);
+
+        daily.set_rounding(Rounding::HalfUp);
+        daily.merge_scaled(&hourly, 0.5).unwrap();
+        assert_eq!(daily.estimate(&2), 3);
+        daily.merge_scaled(&hourly, f64::NAN).unwrap();
+        daily.merge_scaled(&hourly, -1.0).unwrap();
+        assert_eq!(daily.estimate(&1), 150);
+        daily.merge_scaled(&hourly, 2.0).unwrap();
+        assert_eq!(daily.estimate(&1), 200);
+    }
+
+    #[test]
+    fn test_extend_from_iterator() {
+        use crate::CountMinSketch32;
+
+        let lines = ["10.0.0.1 GET", "10.0.0.2 GET", "10.0.0.1 POST"];
+        let cms: CountMinSketch32<&str> = lines
+            .iter()
+            .map(|line| line.split(' ').next().unwrap())
+            .collect();
+        assert_eq!(cms.estimate("10.0.0.1"), 2);
+        assert_eq!(cms.estimate("10.0.0.2"), 1);
+        assert_eq!(cms.total(), 3);
+
+        let mut cms =
+            CountMinSketch32::<u32>::new_with_keys(100, 0.95, 10.0, [1, 2, 3, 4]).unwrap();
+        cms.extend(vec![1, 2, 1]);
+        cms.extend(vec![(2, 5), (3, 7)]);
+        assert_eq!(cms.estimate(&1), 2);
+        assert_eq!(cms.estimate(&2), 6);
+        assert_eq!(cms.estimate(&3), 7);
+
+        let empty: CountMinSketch32<u32> = core::iter::empty().collect();
+        assert_eq!((empty.width(), empty.depth(), empty.total()), (4096, 7, 0));
+        assert_eq!(empty.epsilon(), 1.0 / 2048.0);
+        assert!(empty.failure_probability() < 0.01);
+    }
+
+    #[test]
+    fn test_frequency_counter() {
+        use crate::{CountMinSketch64, FrequencyCounter};
+
+        let cms = CountMinSketch64::new_with_keys(10000, 0.99, 1.0, [1, 2, 3, 4]).unwrap();
+        let mut counter = FrequencyCounter::<String>::from_sketch(cms);
+        assert!(counter.is_empty());
+        assert_eq!(counter.count("a"), 1);
+        assert_eq!(counter.count("a"), 2);
+        assert_eq!(counter.count_by("b", 5), 5);
+        assert_eq!(counter.get("a"), Some(2));
+        assert_eq!(counter.get("c"), None);
+        assert!(counter.contains_key("b"));
+        assert_eq!(counter.total(), 7);
+
+        for i in 0..1000 {
+            counter.count(&i.to_string());
+        }
+        let len = counter.len_estimate();
+        assert!((950..=1050).contains(&len), "{}", len);
+        counter.clear();
+        assert_eq!((counter.len_estimate(), counter.get("a")), (0, None));
+    }
+
+    #[test]
+    fn test_bytes_sketch() {
+        use crate::BytesCountMinSketch;
+
+        let seeds = [1, 2, 3, 4];
+        let mut cms = BytesCountMinSketch::<u32>::new_with_keys(1000, 0.99, 10.0, seeds).unwrap();
+        let packet = [0x45u8, 0x00, 0x01, 0x02, 0x03, 0x04];
+        cms.add_bytes(&packet[2..], 3);
+        cms.increment_bytes(&packet[2..]);
+        assert_eq!(cms.estimate_bytes(&[0x01, 0x02, 0x03, 0x04]), 4);
+        assert_eq!(cms.estimate_bytes(b""), 0);
+        assert_eq!(cms.total(), 4);
+
+        let mut other = BytesCountMinSketch::<u32>::from_config(&cms.config()).unwrap();
+        other.add_bytes(b"key", 2);
+        cms.merge(&other).unwrap();
+        let restored = BytesCountMinSketch::<u32>::from_bytes(&cms.to_bytes()).unwrap();
+        assert_eq!(restored.estimate_bytes(b"key"), 2);
+        assert_eq!(restored.estimate_bytes(&packet[2..]), 4);
+    }
+
+    // Randomized operation sequences checked against exact counts, for all counter widths
+    macro_rules! property_tests {
+        ($name:ident, $CountMinSketch:ident, $Counter:ty) => {
+            mod $name {
+                use crate::{$CountMinSketch, SketchConfig};
+                use rand::rngs::StdRng;
+                use rand::{Rng, SeedableRng};
+                use std::collections::HashMap;
+
+                fn random_ops(
+                    seed: u64,
+                    mut check: impl FnMut(&$CountMinSketch<u32>, &HashMap<u32, u64>),
+                ) {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let capacity = rng.gen_range(10..1000);
+                    let probability = rng.gen_range(0.5..0.999);
+                    let mut cms = $CountMinSketch::<u32>::new(capacity, probability, 10.0).unwrap();
+                    let mut truth = HashMap::new();
+                    for _ in 0..300 {
+                        match rng.gen_range(0..100) {
+                            0 => {
+                                cms.reset();
+                                truth.values_mut().for_each(|count| *count /= 2);
+                            }
+                            _ => {
+                                let key = rng.gen_range(0..50u32);
+                                let value = rng.gen_range(0..20) as $Counter;
+                                cms.add(&key, value);
+                                let count = truth.entry(key).or_insert(0);
+                                *count = (*count + value as u64).min(<$Counter>::MAX as u64);
+                            }
+                        }
+                        check(&cms, &truth);
+                    }
+                }
+
+                #[test]
+                fn never_underestimates() {
+                    for seed in 0..32 {
+                        random_ops(seed, |cms, truth| {
+                            for (key, &count) in truth {
+                                assert!(cms.estimate(key) as u64 >= count);
+                            }
+                        });
+                    }
+                }
+
+                #[test]
+                fn decay_is_monotone() {
+                    for seed in 0..32 {
+                        let mut rng = StdRng::seed_from_u64(seed);
+                        let mut cms = $CountMinSketch::<u32>::new(100, 0.95, 10.0).unwrap();
+                        for _ in 0..200 {
+                            cms.add(&rng.gen_range(0..50u32), rng.gen_range(0..20) as $Counter);
+                        }
+                        let before: Vec<$Counter> = (0..50u32).map(|k| cms.estimate(&k)).collect();
+                        cms.reset();
+                        for (key, &estimate) in (0..50u32).zip(&before) {
+                            assert_eq!(cms.estimate(&key), estimate / 2);
+                        }
+                    }
+                }
+
+                #[test]
+                fn config_round_trips() {
+                    for seed in 0..32 {
+                        random_ops(seed, |cms, _| {
+                            let config = cms.config();
+                            let decoded = SketchConfig::from_bytes(&config.to_bytes()).unwrap();
+                            assert_eq!(decoded, config);
+                            let fresh = $CountMinSketch::<u32>::from_config(&decoded).unwrap();
+                            assert_eq!(cms.is_mergeable(&fresh), Ok(()));
+                        });
+                    }
+                }
+
+                #[test]
+                fn bytes_round_trip() {
+                    for seed in 0..32 {
+                        random_ops(seed, |cms, truth| {
+                            let restored =
+                                $CountMinSketch::<u32>::from_bytes(&cms.to_bytes()).unwrap();
+                            assert_eq!(restored.digest(), cms.digest());
+                            assert_eq!(restored.total(), cms.total());
+                            for key in truth.keys() {
+                                assert_eq!(restored.estimate(key), cms.estimate(key));
+                            }
+                        });
+                    }
+                }
+
+                #[test]
+                fn merge_equals_combined_stream() {
+                    for seed in 0..32 {
+                        let mut rng = StdRng::seed_from_u64(seed);
+                        let seeds = [rng.gen(), rng.gen(), rng.gen(), rng.gen()];
+                        let new = || {
+                            let mut cms =
+                                $CountMinSketch::<u32>::new_with_keys(100, 0.95, 10.0, seeds)
+                                    .unwrap();
+                            cms.set_conservative_update(false);
+                            cms
+                        };
+                        let (mut a, mut b, mut combined) = (new(), new(), new());
+                        for _ in 0..200 {
+                            let key = rng.gen_range(0..50u32);
+                            let value = rng.gen_range(0..20) as $Counter;
+                            if rng.gen() {
+                                a.add(&key, value);
+                            } else {
+                                b.add(&key, value);
+                            }
+                            combined.add(&key, value);
+                        }
+                        a.merge(&b).unwrap();
+                        assert_eq!(a.digest(), combined.digest());
+                        assert_eq!(a.total(), combined.total());
+                    }
+                }
+            }
+        };
+    }
+
+    property_tests!(properties8, CountMinSketch8, u8);
+    property_tests!(properties16, CountMinSketch16, u16);
+    property_tests!(properties32, CountMinSketch32, u32);
+    property_tests!(properties64, CountMinSketch64, u64);
 }
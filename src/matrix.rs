@@ -0,0 +1,67 @@
+use alloc::vec::Vec;
+#[cfg(feature = "export")]
+use std::io::{self, Write};
+
+/// Counters of a sketch, with its dimensions and hash keys, from
+/// `CountMinSketch::export_matrix()`.
+///
+/// `rows[k][i]` is the counter `i` of row `k`. With the `export` feature,
+/// the matrix can be written as CSV or JSON for offline analysis.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CounterMatrix {
+    pub width: usize,
+    pub depth: usize,
+    /// In the order of `SketchConfig::seeds`.
+    pub seeds: [u64; 4],
+    pub rows: Vec<Vec<u64>>,
+}
+
+#[cfg(feature = "export")]
+impl CounterMatrix {
+    /// One line of comma-separated counters per row, after a `#` comment
+    /// line with the dimensions and seeds.
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let [s0, s1, s2, s3] = self.seeds;
+        writeln!(
+            out,
+            "# width={} depth={} seeds={},{},{},{}",
+            self.width, self.depth, s0, s1, s2, s3
+        )?;
+        for row in &self.rows {
+            write_list(&mut out, row)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// A single object with the `width`, `depth`, `seeds` and `rows` fields.
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(
+            out,
+            "{{\"width\":{},\"depth\":{},\"seeds\":[",
+            self.width, self.depth
+        )?;
+        write_list(&mut out, &self.seeds)?;
+        write!(out, "],\"rows\":[")?;
+        for (k_i, row) in self.rows.iter().enumerate() {
+            if k_i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "[")?;
+            write_list(&mut out, row)?;
+            write!(out, "]")?;
+        }
+        writeln!(out, "]}}")
+    }
+}
+
+#[cfg(feature = "export")]
+fn write_list<W: Write>(out: &mut W, values: &[u64]) -> io::Result<()> {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{}", value)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use alloc::borrow::Cow;
+use alloc::string::String;
+use core::hash::{Hash, Hasher};
+
+/// Feeds a key to the sketch hashers, possibly after normalizing it.
+pub trait NormalizeKey<Q: ?Sized> {
+    fn hash_into<H: Hasher>(&self, key: &Q, states: &mut [H]);
+}
+
+/// Keys are hashed as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoNormalizer;
+
+impl<Q> NormalizeKey<Q> for NoNormalizer
+where
+    Q: Hash + ?Sized,
+{
+    #[inline]
+    fn hash_into<H: Hasher>(&self, key: &Q, states: &mut [H]) {
+        for state in states {
+            key.hash(state);
+        }
+    }
+}
+
+/// Canonicalizes string keys before hashing, so that trivially different
+/// spellings are counted together.
+///
+/// Normalized keys hash like the equivalent plain strings.
+pub trait KeyNormalizer {
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str>;
+}
+
+impl<T> NormalizeKey<str> for T
+where
+    T: KeyNormalizer,
+{
+    fn hash_into<H: Hasher>(&self, key: &str, states: &mut [H]) {
+        let key = self.normalize(key);
+        for state in states {
+            key.hash(state);
+        }
+    }
+}
+
+impl<T> NormalizeKey<String> for T
+where
+    T: KeyNormalizer,
+{
+    fn hash_into<H: Hasher>(&self, key: &String, states: &mut [H]) {
+        NormalizeKey::<str>::hash_into(self, key.as_str(), states)
+    }
+}
+
+impl<F> KeyNormalizer for F
+where
+    F: for<'a> Fn(&'a str) -> Cow<'a, str>,
+{
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        self(key)
+    }
+}
+
+/// Applies `A`, then `B`.
+impl<A, B> KeyNormalizer for (A, B)
+where
+    A: KeyNormalizer,
+    B: KeyNormalizer,
+{
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match self.0.normalize(key) {
+            Cow::Borrowed(key) => self.1.normalize(key),
+            Cow::Owned(key) => Cow::Owned(self.1.normalize(&key).into_owned()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lowercase;
+
+impl KeyNormalizer for Lowercase {
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if key.chars().any(char::is_uppercase) {
+            Cow::Owned(key.to_lowercase())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trim;
+
+impl KeyNormalizer for Trim {
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(key.trim())
+    }
+}
@@ -0,0 +1,38 @@
+/// Maps counters in `0..=max` to 8-bit codes on a logarithmic scale.
+///
+/// Codes decode to the smallest representable value that is not lower than
+/// the original counter, so decoded sketches never underestimate. With
+/// `base = (1 + max)^(1/255)`, `1 + decoded <= base * (1 + original) + 1`.
+pub(crate) struct LogQuantizer {
+    max: u64,
+}
+
+impl LogQuantizer {
+    pub(crate) fn new(max: u64) -> Self {
+        LogQuantizer { max }
+    }
+
+    pub(crate) fn decode(&self, code: u8) -> u64 {
+        match code {
+            0 => 0,
+            255 => self.max,
+            _ => {
+                let value = (self.max as f64 + 1.0).powf(code as f64 / 255.0) - 1.0;
+                (value.ceil() as u64).min(self.max)
+            }
+        }
+    }
+
+    pub(crate) fn encode(&self, counter: u64) -> u8 {
+        let (mut lo, mut hi) = (0u8, 255u8);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.decode(mid) >= counter {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        lo
+    }
+}
@@ -0,0 +1,48 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Uniform random sample of up to `capacity` keys out of all the keys offered
+/// so far (Algorithm R).
+pub(crate) struct Reservoir<K> {
+    keys: Vec<K>,
+    capacity: usize,
+    seen: u64,
+    rng: StdRng,
+}
+
+impl<K> Reservoir<K> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Reservoir {
+            keys: Vec::with_capacity(capacity),
+            capacity,
+            seen: 0,
+            rng: StdRng::from_rng(rand::thread_rng()).expect("Unable to seed the reservoir"),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.keys.clear();
+        self.seen = 0;
+    }
+}
+
+impl<K> Reservoir<K>
+where
+    K: Clone,
+{
+    pub(crate) fn offer(&mut self, key: &K) {
+        self.seen += 1;
+        if self.keys.len() < self.capacity {
+            self.keys.push(key.clone());
+            return;
+        }
+        let idx = self.rng.gen_range(0..self.seen);
+        if idx < self.capacity as u64 {
+            self.keys[idx as usize] = key.clone();
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use core::borrow::Borrow;
+use core::convert::TryFrom;
+use core::hash::Hash;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    key_hashes, row_offset, AtomicCounter, CmsError, CountMinSketch64, DecayPolicy, FastHasher,
+    OverflowPolicy, Rounding, SketchConfig,
+};
+
+const SHARED_MAGIC: u64 = u64::from_le_bytes(*b"CMSHARE1");
+// Magic, width, depth, four seeds and total
+const HEADER_WORDS: usize = 8;
+
+/// Sketch stored entirely in a caller-supplied region of `AtomicU64`s, so
+/// that several processes can update it, for example the workers of a
+/// pre-fork server sharing an anonymous `MAP_SHARED` mapping.
+///
+/// One process calls `init()` on the region, before the others `attach()`
+/// to it: dimensions and hash keys are stored in the region itself.
+/// Updates behave as with `AtomicCountMinSketch`, without conservative
+/// update and with relaxed ordering.
+///
+/// A mapping is turned into a region with
+/// `core::slice::from_raw_parts(ptr as *const AtomicU64, len)`, which
+/// requires 8-byte alignment; `region_len()` gives `len`.
+pub struct SharedCountMinSketch<'a, K> {
+    header: &'a [AtomicU64],
+    counters: &'a [AtomicU64],
+    hashers: [FastHasher; 2],
+    mask: usize,
+    k_num: usize,
+    phantom_k: PhantomData<K>,
+}
+
+impl<'a, K> SharedCountMinSketch<'a, K>
+where
+    K: Hash,
+{
+    /// Number of `AtomicU64` words needed by a sketch of these parameters.
+    pub fn region_len(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+    ) -> Result<usize, CmsError> {
+        let len = CountMinSketch64::<K>::buffer_len(capacity, probability, tolerance)?;
+        Ok(HEADER_WORDS + len)
+    }
+
+    /// Initializes an empty sketch in `region`, with random hash keys.
+    #[cfg(feature = "std")]
+    pub fn init(
+        region: &'a [AtomicU64],
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+    ) -> Result<Self, CmsError> {
+        let (k0, k1) = CountMinSketch64::<K>::sip_new().keys();
+        let (k2, k3) = CountMinSketch64::<K>::sip_new().keys();
+        Self::init_with_keys(region, capacity, probability, tolerance, [k0, k1, k2, k3])
+    }
+
+    /// Same as `init()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    ///
+    /// Processes must not use the region while it is being initialized.
+    pub fn init_with_keys(
+        region: &'a [AtomicU64],
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let (width, k_num) = CountMinSketch64::<K>::dimensions(capacity, probability, tolerance)?;
+        let len = HEADER_WORDS + width * k_num;
+        if region.len() < len {
+            return Err(CmsError::BufferTooSmall { needed: len });
+        }
+        let header = [
+            width as u64,
+            k_num as u64,
+            seeds[0],
+            seeds[1],
+            seeds[2],
+            seeds[3],
+            0,
+        ];
+        for (word, &value) in region[1..].iter().zip(&header) {
+            word.store(value, Ordering::Relaxed);
+        }
+        for counter in &region[HEADER_WORDS..len] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        // Published last, so that attaching never sees a partial header
+        region[0].store(SHARED_MAGIC, Ordering::Release);
+        Ok(Self::attach(region).expect("The region was just initialized"))
+    }
+
+    /// Opens a sketch previously initialized in `region`.
+    pub fn attach(region: &'a [AtomicU64]) -> Result<Self, &'static str> {
+        if region.len() < HEADER_WORDS || region[0].load(Ordering::Acquire) != SHARED_MAGIC {
+            return Err("Not an initialized shared sketch");
+        }
+        let word = |i: usize| region[i].load(Ordering::Relaxed);
+        let width = usize::try_from(word(1)).map_err(|_| "Width is too large")?;
+        let k_num = usize::try_from(word(2)).map_err(|_| "Depth is too large")?;
+        CountMinSketch64::<K>::check_dimensions(width, k_num)
+            .map_err(|_| "Invalid sketch dimensions")?;
+        let (header, counters) = region.split_at(HEADER_WORDS);
+        let counters = counters
+            .get(..width * k_num)
+            .ok_or("The region is smaller than the sketch")?;
+        Ok(SharedCountMinSketch {
+            header,
+            counters,
+            hashers: [
+                FastHasher::new_with_keys(word(3), word(4)),
+                FastHasher::new_with_keys(word(5), word(6)),
+            ],
+            mask: width - 1,
+            k_num,
+            phantom_k: PhantomData,
+        })
+    }
+
+    pub fn config(&self) -> SketchConfig {
+        let (k0, k1) = self.hashers[0].keys();
+        let (k2, k3) = self.hashers[1].keys();
+        SketchConfig {
+            width: (self.mask + 1) as u64,
+            depth: self.k_num as u64,
+            seeds: [k0, k1, k2, k3],
+            counter_size: 8,
+            ceiling: u64::MAX,
+            conservative: false,
+            rounding: Rounding::Down,
+            overflow_policy: OverflowPolicy::Saturate,
+            decay_policy: DecayPolicy::Never,
+        }
+    }
+
+    pub fn add<Q>(&self, key: &Q, value: u64)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.total_word().saturating_add(value);
+        let hashes = key_hashes(&self.hashers, key);
+        for k_i in 0..self.k_num {
+            self.counters[self.cell(&hashes, k_i)].saturating_add(value);
+        }
+    }
+
+    pub fn increment<Q>(&self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.add(key, 1)
+    }
+
+    pub fn estimate<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = key_hashes(&self.hashers, key);
+        (0..self.k_num)
+            .map(|k_i| self.counters[self.cell(&hashes, k_i)].load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total_word().load(Ordering::Relaxed)
+    }
+
+    /// Zeroes the counters, keeping the hash keys. Concurrent updates may or
+    /// may not survive.
+    pub fn clear(&self) {
+        for counter in self.counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.total_word().store(0, Ordering::Relaxed);
+    }
+
+    /// Copies the current counters into a regular sketch. The copy isn't an
+    /// atomic snapshot.
+    pub fn snapshot(&self) -> CountMinSketch64<K> {
+        let mut cms = CountMinSketch64::from_config(&self.config())
+            .expect("Configuration matches the counter type");
+        for (counter, atomic) in cms.counters.as_mut_slice().iter_mut().zip(self.counters) {
+            *counter = atomic.load(Ordering::Relaxed);
+        }
+        cms.total = self.total();
+        cms
+    }
+
+    #[inline]
+    fn total_word(&self) -> &AtomicU64 {
+        &self.header[HEADER_WORDS - 1]
+    }
+
+    #[inline]
+    fn cell(&self, hashes: &[u64; 2], k_i: usize) -> usize {
+        k_i * (self.mask + 1) + row_offset(hashes, k_i, self.mask)
+    }
+}
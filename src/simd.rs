@@ -0,0 +1,145 @@
+use core::cmp::min;
+
+use crate::{Counter, Rounding};
+
+pub(crate) fn halve_scalar<C: Counter>(counters: &mut [C], rounding: Rounding) {
+    for counter in counters {
+        *counter = C::from_u64(rounding.halve(counter.to_u64())).unwrap_or(*counter);
+    }
+}
+
+pub(crate) fn add_scalar<C: Counter>(counters: &mut [C], values: &[C], ceiling: C) {
+    for (counter, &value) in counters.iter_mut().zip(values) {
+        *counter = min(counter.saturating_add(value), ceiling);
+    }
+}
+
+#[cfg(not(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+)))]
+pub(crate) use self::{
+    add_scalar as add_u16, add_scalar as add_u8, halve_scalar as halve_u16,
+    halve_scalar as halve_u8,
+};
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+))]
+pub(crate) use self::sse2::{add_u16, add_u8, halve_u16, halve_u8};
+
+// 16 bytes at a time, the remainder of a row going through the scalar loop.
+// SSE2 is part of the target features, so the intrinsics are always
+// available, and loads and stores are unaligned.
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+))]
+mod sse2 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+    use core::cmp::min;
+    use core::mem;
+
+    use super::{add_scalar, halve_scalar};
+    use crate::Rounding;
+
+    macro_rules! sse2_impl {
+        (
+            $halve:ident,
+            $add:ident,
+            $C:ty,
+            $set1:ident,
+            $shr1:expr,
+            $avg:ident,
+            $cmpeq:ident,
+            $add_wrapping:ident,
+            $adds:ident,
+            $subs:ident
+        ) => {
+            pub(crate) fn $halve(counters: &mut [$C], rounding: Rounding) {
+                const LANES: usize = 16 / mem::size_of::<$C>();
+                let split = counters.len() - counters.len() % LANES;
+                let (head, tail) = counters.split_at_mut(split);
+                for chunk in head.chunks_exact_mut(LANES) {
+                    let ptr = chunk.as_mut_ptr() as *mut __m128i;
+                    unsafe {
+                        let v = _mm_loadu_si128(ptr);
+                        let down = $shr1(v);
+                        let halved = match rounding {
+                            Rounding::Down => down,
+                            Rounding::HalfUp => $avg(v, _mm_setzero_si128()),
+                            // Only a count of 1 halves to 0
+                            Rounding::KeepNonZero => {
+                                let ones = $set1(1);
+                                $add_wrapping(down, _mm_and_si128($cmpeq(v, ones), ones))
+                            }
+                        };
+                        _mm_storeu_si128(ptr, halved);
+                    }
+                }
+                halve_scalar(tail, rounding);
+            }
+
+            pub(crate) fn $add(counters: &mut [$C], values: &[$C], ceiling: $C) {
+                const LANES: usize = 16 / mem::size_of::<$C>();
+                let len = min(counters.len(), values.len());
+                let split = len - len % LANES;
+                let (head, tail) = counters[..len].split_at_mut(split);
+                for (chunk, other) in head.chunks_exact_mut(LANES).zip(values.chunks_exact(LANES)) {
+                    let ptr = chunk.as_mut_ptr() as *mut __m128i;
+                    unsafe {
+                        let sum = $adds(
+                            _mm_loadu_si128(ptr),
+                            _mm_loadu_si128(other.as_ptr() as *const __m128i),
+                        );
+                        // min(a, b) = a - (a - b), with saturating subtractions
+                        let capped = $subs(sum, $subs(sum, $set1(ceiling as _)));
+                        _mm_storeu_si128(ptr, capped);
+                    }
+                }
+                add_scalar(tail, &values[split..len], ceiling);
+            }
+        };
+    }
+
+    // There is no 8-bit shift: shift 16-bit lanes, and drop the bits that
+    // crossed into the neighbouring byte
+    #[inline]
+    unsafe fn shr1_u8(v: __m128i) -> __m128i {
+        _mm_and_si128(_mm_srli_epi16::<1>(v), _mm_set1_epi8(0x7f))
+    }
+
+    #[inline]
+    unsafe fn shr1_u16(v: __m128i) -> __m128i {
+        _mm_srli_epi16::<1>(v)
+    }
+
+    sse2_impl!(
+        halve_u8,
+        add_u8,
+        u8,
+        _mm_set1_epi8,
+        shr1_u8,
+        _mm_avg_epu8,
+        _mm_cmpeq_epi8,
+        _mm_add_epi8,
+        _mm_adds_epu8,
+        _mm_subs_epu8
+    );
+    sse2_impl!(
+        halve_u16,
+        add_u16,
+        u16,
+        _mm_set1_epi16,
+        shr1_u16,
+        _mm_avg_epu16,
+        _mm_cmpeq_epi16,
+        _mm_add_epi16,
+        _mm_adds_epu16,
+        _mm_subs_epu16
+    );
+}
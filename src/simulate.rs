@@ -0,0 +1,76 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{CmsError, CountMinSketch64};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SketchParams {
+    pub capacity: usize,
+    pub probability: f64,
+    pub tolerance: f64,
+}
+
+/// Synthetic stream of `length` items drawn from `distinct_keys` keys with
+/// Zipf-distributed frequencies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamModel {
+    pub distinct_keys: usize,
+    pub length: usize,
+    pub zipf_exponent: f64,
+    pub seed: u64,
+}
+
+/// Overestimation (estimate minus true count) percentiles across all keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccuracyReport {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub mean: f64,
+}
+
+pub fn simulate_accuracy(
+    params: SketchParams,
+    stream_model: StreamModel,
+) -> Result<AccuracyReport, CmsError> {
+    if stream_model.distinct_keys == 0 {
+        return Err(CmsError::DistinctKeys(stream_model.distinct_keys));
+    }
+    if !stream_model.zipf_exponent.is_finite() || stream_model.zipf_exponent < 0.0 {
+        return Err(CmsError::ZipfExponent(stream_model.zipf_exponent));
+    }
+    let mut cms =
+        CountMinSketch64::<u64>::new(params.capacity, params.probability, params.tolerance)?;
+    let mut cdf = Vec::with_capacity(stream_model.distinct_keys);
+    let mut sum = 0.0;
+    for rank in 1..=stream_model.distinct_keys {
+        sum += 1.0 / (rank as f64).powf(stream_model.zipf_exponent);
+        cdf.push(sum);
+    }
+    let mut rng = StdRng::seed_from_u64(stream_model.seed);
+    let mut counts = vec![0u64; stream_model.distinct_keys];
+    for _ in 0..stream_model.length {
+        let x = rng.gen::<f64>() * sum;
+        let key = cdf
+            .partition_point(|&c| c < x)
+            .min(stream_model.distinct_keys - 1);
+        counts[key] += 1;
+        cms.increment(&(key as u64));
+    }
+    let mut errors: Vec<u64> = counts
+        .iter()
+        .enumerate()
+        .map(|(key, &count)| cms.estimate(&(key as u64)) - count)
+        .collect();
+    errors.sort_unstable();
+    let percentile = |p: usize| errors[(errors.len() - 1) * p / 100];
+    let mean = errors.iter().sum::<u64>() as f64 / errors.len() as f64;
+    Ok(AccuracyReport {
+        p50: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+        max: percentile(100),
+        mean,
+    })
+}
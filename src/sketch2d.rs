@@ -0,0 +1,57 @@
+use std::hash::Hash;
+
+use crate::{CmsError, CountMinSketch64};
+
+/// Count-min sketch keyed by `(row_key, col_key)` pairs, with an auxiliary
+/// sketch answering marginal queries over a row.
+pub struct CountMinSketch2D<R, C> {
+    cells: CountMinSketch64<(R, C)>,
+    rows: CountMinSketch64<R>,
+}
+
+impl<R, C> CountMinSketch2D<R, C>
+where
+    R: Hash,
+    C: Hash,
+{
+    pub fn new(capacity: usize, probability: f64, tolerance: f64) -> Result<Self, CmsError> {
+        Ok(CountMinSketch2D {
+            cells: CountMinSketch64::new(capacity, probability, tolerance)?,
+            rows: CountMinSketch64::new(capacity, probability, tolerance)?,
+        })
+    }
+
+    pub fn add(&mut self, row_key: &R, col_key: &C, value: u64) {
+        let hashes = self.cells.key_hashes(&(row_key, col_key));
+        self.cells.add_hashes(&hashes, value);
+        let hashes = self.rows.key_hashes(row_key);
+        self.rows.add_hashes(&hashes, value);
+    }
+
+    pub fn increment(&mut self, row_key: &R, col_key: &C) {
+        self.add(row_key, col_key, 1)
+    }
+
+    pub fn estimate(&self, row_key: &R, col_key: &C) -> u64 {
+        self.cells
+            .estimate_hashes(&self.cells.key_hashes(&(row_key, col_key)))
+    }
+
+    pub fn estimate_row(&self, row_key: &R) -> u64 {
+        self.rows.estimate_hashes(&self.rows.key_hashes(row_key))
+    }
+
+    pub fn total(&self) -> u64 {
+        self.rows.total()
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.rows.clear();
+    }
+
+    pub fn reset(&mut self) {
+        self.cells.reset();
+        self.rows.reset();
+    }
+}
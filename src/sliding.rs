@@ -0,0 +1,108 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use crate::{CmsError, CountMinSketch64, Storage};
+
+/// Sketch of the last `slots` periods of a stream, as a ring of sub-sketches
+/// sharing the same hash keys.
+///
+/// Updates go to the current slot. `tick()` starts a new period, dropping the
+/// oldest one, and estimates only cover the periods still in the window.
+pub struct SlidingCountMinSketch<K> {
+    slots: Vec<CountMinSketch64<K>>,
+    current: usize,
+}
+
+impl<K> SlidingCountMinSketch<K>
+where
+    K: Hash,
+{
+    pub fn new(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        slots: usize,
+    ) -> Result<Self, CmsError> {
+        if slots == 0 {
+            return Err(CmsError::Slots(slots));
+        }
+        let first = CountMinSketch64::new(capacity, probability, tolerance)?;
+        let config = first.config();
+        let mut ring = Vec::with_capacity(slots);
+        ring.push(first);
+        for _ in 1..slots {
+            ring.push(CountMinSketch64::from_config(&config)?);
+        }
+        Ok(SlidingCountMinSketch {
+            slots: ring,
+            current: 0,
+        })
+    }
+
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn add<Q>(&mut self, key: &Q, value: u64)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.slots[self.current].add(key, value)
+    }
+
+    pub fn increment<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.add(key, 1)
+    }
+
+    /// Estimate over the whole window: the lowest row of the counters summed
+    /// across slots, which is never above the sum of per-slot estimates.
+    pub fn estimate<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let first = &self.slots[0];
+        let hashes = first.key_hashes(key);
+        (0..first.k_num)
+            .map(|k_i| {
+                let offset = first.row_offset(&hashes, k_i);
+                self.slots.iter().fold(0u64, |sum, slot| {
+                    sum.saturating_add(slot.counters.row(k_i)[offset])
+                })
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Sum of the values added during the window.
+    pub fn total(&self) -> u64 {
+        self.slots
+            .iter()
+            .fold(0u64, |sum, slot| sum.saturating_add(slot.total()))
+    }
+
+    /// Starts a new period, forgetting the oldest one.
+    pub fn tick(&mut self) {
+        self.current = (self.current + 1) % self.slots.len();
+        self.slots[self.current].clear_keep_seeds();
+    }
+
+    /// Same as calling `tick()` `periods` times.
+    pub fn advance(&mut self, periods: usize) {
+        // After a full turn, every slot has been cleared
+        for _ in 0..periods.min(self.slots.len()) {
+            self.tick();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for slot in &mut self.slots {
+            slot.clear_keep_seeds();
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::{Clock, CmsError, CountMinSketch64};
+
+struct Checkpoint {
+    at: Duration,
+    // (cell index, increment since the previous checkpoint), sorted by cell index
+    deltas: Vec<(usize, u64)>,
+}
+
+/// Sketch keeping its history as sparse per-epoch deltas, so that past
+/// states can be queried with `estimate_at()`.
+///
+/// A checkpoint is taken at every epoch boundary crossed by `add()`.
+pub struct TemporalSketch<K> {
+    cms: CountMinSketch64<K>,
+    snapshot: Vec<u64>,
+    checkpoints: Vec<Checkpoint>,
+    epoch: Duration,
+    epoch_start: Duration,
+}
+
+impl<K> TemporalSketch<K>
+where
+    K: Hash,
+{
+    pub fn new(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        epoch: Duration,
+        start: Duration,
+    ) -> Result<Self, CmsError> {
+        if epoch.is_zero() {
+            return Err(CmsError::ZeroDuration);
+        }
+        let cms = CountMinSketch64::new(capacity, probability, tolerance)?;
+        let snapshot = vec![0; (cms.mask + 1) * cms.k_num];
+        Ok(TemporalSketch {
+            cms,
+            snapshot,
+            checkpoints: Vec::new(),
+            epoch,
+            epoch_start: start,
+        })
+    }
+
+    pub fn add<Q, T>(&mut self, key: &Q, value: u64, clock: T)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        let now = clock.now();
+        self.advance(now);
+        self.cms.add(key, value);
+    }
+
+    pub fn increment<Q, T>(&mut self, key: &Q, clock: T)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+        T: Clock,
+    {
+        self.add(key, 1, clock)
+    }
+
+    /// Current estimate, including the epoch in progress.
+    pub fn estimate<Q>(&self, key: &Q) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.cms.estimate(key)
+    }
+
+    /// Estimate as of the last checkpoint taken at or before `t`.
+    pub fn estimate_at<Q>(&self, key: &Q, t: Duration) -> u64
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        let hashes = self.cms.key_hashes(key);
+        let width = self.cms.mask + 1;
+        let checkpoints = self.checkpoints.iter().take_while(|c| c.at <= t);
+        let mut counts = vec![0u64; self.cms.k_num];
+        for checkpoint in checkpoints {
+            for (k_i, count) in counts.iter_mut().enumerate() {
+                let cell = k_i * width + self.cms.row_offset(&hashes, k_i);
+                if let Ok(i) = checkpoint.deltas.binary_search_by_key(&cell, |d| d.0) {
+                    *count += checkpoint.deltas[i].1;
+                }
+            }
+        }
+        counts.into_iter().min().unwrap_or(0)
+    }
+
+    /// Closes the epochs ending at or before `clock.now()`.
+    pub fn advance<T>(&mut self, clock: T)
+    where
+        T: Clock,
+    {
+        let now = clock.now();
+        if now < self.epoch_start + self.epoch {
+            return;
+        }
+        self.checkpoint(self.epoch_start + self.epoch);
+        let epoch = self.epoch.as_nanos();
+        let elapsed_epochs = (now - self.epoch_start).as_nanos() / epoch;
+        self.epoch_start =
+            Duration::from_nanos((self.epoch_start.as_nanos() + elapsed_epochs * epoch) as u64);
+    }
+
+    pub fn checkpoints(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.checkpoints.iter().map(|c| c.at)
+    }
+
+    fn checkpoint(&mut self, at: Duration) {
+        let mut deltas = Vec::new();
+        for (cell, &counter) in self.cms.counters.as_slice().iter().enumerate() {
+            if counter != self.snapshot[cell] {
+                deltas.push((cell, counter - self.snapshot[cell]));
+                self.snapshot[cell] = counter;
+            }
+        }
+        if !deltas.is_empty() {
+            self.checkpoints.push(Checkpoint { at, deltas });
+        }
+    }
+}
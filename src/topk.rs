@@ -0,0 +1,148 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+use crate::{CmsError, CountMinSketch64, Incompatibility};
+
+/// The `k` most frequent keys of a stream, according to a sketch.
+///
+/// Candidates are kept in a min-heap, so a new key only displaces the
+/// lightest tracked one. Updating a tracked key leaves its previous heap
+/// entry behind; stale entries are skipped, and dropped when the heap
+/// grows to twice `k`.
+pub struct TopK<K> {
+    cms: CountMinSketch64<K>,
+    k: usize,
+    scores: HashMap<K, u64>,
+    heap: BinaryHeap<Reverse<(u64, K)>>,
+}
+
+impl<K> TopK<K>
+where
+    K: Hash + Eq + Ord + Clone,
+{
+    pub fn new(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        k: usize,
+    ) -> Result<Self, CmsError> {
+        let cms = CountMinSketch64::new(capacity, probability, tolerance)?;
+        Ok(Self::with_sketch(cms, k))
+    }
+
+    /// Same as `new()`, with fixed hash keys, so that trackers built with
+    /// the same seeds can be merged.
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        k: usize,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let cms = CountMinSketch64::new_with_keys(capacity, probability, tolerance, seeds)?;
+        Ok(Self::with_sketch(cms, k))
+    }
+
+    fn with_sketch(cms: CountMinSketch64<K>, k: usize) -> Self {
+        TopK {
+            cms,
+            k,
+            scores: HashMap::with_capacity(k),
+            heap: BinaryHeap::with_capacity(2 * k),
+        }
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn add(&mut self, key: &K, value: u64) {
+        self.cms.add(key, value);
+        let score = self.cms.estimate(key);
+        self.offer(key, score);
+    }
+
+    pub fn insert(&mut self, key: &K) {
+        self.add(key, 1)
+    }
+
+    pub fn estimate(&self, key: &K) -> u64 {
+        self.cms.estimate(key)
+    }
+
+    /// Up to `n` of the tracked keys with their estimates, most frequent
+    /// first. Ties are ordered by key.
+    pub fn top(&self, n: usize) -> Vec<(&K, u64)> {
+        let mut top: Vec<_> = self
+            .scores
+            .iter()
+            .map(|(key, &score)| (key, score))
+            .collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// Adds the sketch of `other`, which must have the same dimensions and
+    /// seeds, and keeps the `k` heaviest of both sets of tracked keys.
+    pub fn merge(&mut self, other: &TopK<K>) -> Result<(), Incompatibility> {
+        self.cms.merge(&other.cms)?;
+        let candidates: Vec<K> = self
+            .scores
+            .keys()
+            .chain(other.scores.keys())
+            .cloned()
+            .collect();
+        self.scores.clear();
+        self.heap.clear();
+        for key in candidates {
+            let score = self.cms.estimate(&key);
+            self.offer(&key, score);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.cms.clear();
+        self.scores.clear();
+        self.heap.clear();
+    }
+
+    fn offer(&mut self, key: &K, score: u64) {
+        if let Some(tracked) = self.scores.get_mut(key) {
+            *tracked = score;
+        } else if self.scores.len() < self.k {
+            self.scores.insert(key.clone(), score);
+        } else {
+            match self.lightest() {
+                Some(lightest) if lightest < score => {
+                    if let Some(Reverse((_, evicted))) = self.heap.pop() {
+                        self.scores.remove(&evicted);
+                    }
+                    self.scores.insert(key.clone(), score);
+                }
+                _ => return,
+            }
+        }
+        self.heap.push(Reverse((score, key.clone())));
+        if self.heap.len() > 2 * self.k {
+            self.heap = self
+                .scores
+                .iter()
+                .map(|(key, &score)| Reverse((score, key.clone())))
+                .collect();
+        }
+    }
+
+    // Drops stale entries until the top of the heap is a tracked key
+    fn lightest(&mut self) -> Option<u64> {
+        while let Some(Reverse((score, key))) = self.heap.peek() {
+            if self.scores.get(key) == Some(score) {
+                return Some(*score);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+}
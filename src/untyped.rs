@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use core::hash::{BuildHasher, Hasher};
+
+use crate::{CmsError, CountMinSketch, Counter, Incompatibility, SketchConfig};
+
+/// Sketch of raw byte keys, such as wire-format identifiers, without a key
+/// type.
+///
+/// Keys are fed to SipHash as they are, with no `Hash` implementation or
+/// length prefix in between, so they hash differently than `&[u8]` keys in
+/// a `CountMinSketch`: only sketches of this type can be merged together.
+/// Configurations and serialized sketches are the same as for
+/// `CountMinSketch`.
+pub struct BytesCountMinSketch<C> {
+    cms: CountMinSketch<(), C>,
+}
+
+impl<C> BytesCountMinSketch<C>
+where
+    C: Counter,
+{
+    #[cfg(feature = "std")]
+    pub fn new(capacity: usize, probability: f64, tolerance: f64) -> Result<Self, CmsError> {
+        Ok(BytesCountMinSketch {
+            cms: CountMinSketch::new(capacity, probability, tolerance)?,
+        })
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(
+        capacity: usize,
+        probability: f64,
+        tolerance: f64,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        Ok(BytesCountMinSketch {
+            cms: CountMinSketch::new_with_keys(capacity, probability, tolerance, seeds)?,
+        })
+    }
+
+    pub fn from_config(config: &SketchConfig) -> Result<Self, CmsError> {
+        Ok(BytesCountMinSketch {
+            cms: CountMinSketch::from_config(config)?,
+        })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        Ok(BytesCountMinSketch {
+            cms: CountMinSketch::from_bytes(bytes)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.cms.to_bytes()
+    }
+
+    pub fn config(&self) -> SketchConfig {
+        self.cms.config()
+    }
+
+    pub fn width(&self) -> usize {
+        self.cms.width()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.cms.depth()
+    }
+
+    pub fn add_bytes(&mut self, key: &[u8], value: C) {
+        let hashes = self.key_hashes(key);
+        self.cms.add_hashes(&hashes, value);
+    }
+
+    pub fn increment_bytes(&mut self, key: &[u8]) {
+        self.add_bytes(key, C::ONE)
+    }
+
+    pub fn estimate_bytes(&self, key: &[u8]) -> C {
+        self.cms.estimate_hashes(&self.key_hashes(key))
+    }
+
+    /// Adds the counters of `other`, which must have the same dimensions
+    /// and seeds, as with `CountMinSketch::merge()`.
+    pub fn merge(&mut self, other: &BytesCountMinSketch<C>) -> Result<(), Incompatibility> {
+        self.cms.merge(&other.cms)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.cms.total()
+    }
+
+    /// Zeroes the counters, keeping the hash keys.
+    pub fn clear(&mut self) {
+        self.cms.clear_keep_seeds();
+    }
+
+    fn key_hashes(&self, key: &[u8]) -> [u64; 2] {
+        let mut hashes = [0u64, 0u64];
+        for (hash, hasher) in hashes.iter_mut().zip(&self.cms.hashers) {
+            let mut sip = hasher.build_hasher();
+            sip.write(key);
+            *hash = sip.finish();
+        }
+        hashes
+    }
+}
@@ -0,0 +1,92 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use crate::{CmsError, CountMinSketch8, TinyLfuAdmission};
+
+/// Two-generation frequency sketch for W-TinyLFU style cache admission.
+///
+/// Accesses are counted in a window sketch. Every `window_size` accesses,
+/// the window is folded into a `TinyLfuAdmission` filter and cleared, so
+/// that the filter ages them as its own accesses. Both sketches share their
+/// dimensions and seeds, and their counters are capped at 15.
+pub struct WindowedCountMin<K> {
+    window: CountMinSketch8<K>,
+    main: TinyLfuAdmission<K>,
+    window_size: usize,
+    window_accesses: usize,
+}
+
+impl<K> WindowedCountMin<K>
+where
+    K: Hash,
+{
+    pub fn new(capacity: usize, window_size: usize) -> Result<Self, CmsError> {
+        let main = CountMinSketch8::new(capacity, 0.95, 2.0)?;
+        Self::with_main(main, capacity, window_size)
+    }
+
+    /// Same as `new()`, with fixed hash keys (in the order of
+    /// `SketchConfig::seeds`).
+    pub fn new_with_keys(
+        capacity: usize,
+        window_size: usize,
+        seeds: [u64; 4],
+    ) -> Result<Self, CmsError> {
+        let main = CountMinSketch8::new_with_keys(capacity, 0.95, 2.0, seeds)?;
+        Self::with_main(main, capacity, window_size)
+    }
+
+    fn with_main(
+        main: CountMinSketch8<K>,
+        capacity: usize,
+        window_size: usize,
+    ) -> Result<Self, CmsError> {
+        let main = TinyLfuAdmission::with_sketch(main, capacity);
+        let window = CountMinSketch8::from_config(&main.sketch().config())?;
+        Ok(WindowedCountMin {
+            window,
+            main,
+            window_size: window_size.max(1),
+            window_accesses: 0,
+        })
+    }
+
+    pub fn record_access<Q>(&mut self, key: &Q)
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.window.increment(key);
+        self.window_accesses += 1;
+        if self.window_accesses < self.window_size {
+            return;
+        }
+        self.main.record_window(&self.window, self.window_accesses);
+        self.window.clear_keep_seeds();
+        self.window_accesses = 0;
+    }
+
+    /// Combined frequency of `key` in both generations.
+    pub fn frequency<Q>(&self, key: &Q) -> u8
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.window.estimate(key) + self.main.frequency(key)
+    }
+
+    /// Returns `true` if `candidate` has been seen more often than `victim`.
+    pub fn admit<Q>(&self, candidate: &Q, victim: &Q) -> bool
+    where
+        Q: Hash + ?Sized,
+        K: Borrow<Q>,
+    {
+        self.frequency(candidate) > self.frequency(victim)
+    }
+
+    pub fn clear(&mut self) {
+        self.window.clear_keep_seeds();
+        self.main.clear_keep_seeds();
+        self.window_accesses = 0;
+    }
+}